@@ -5,6 +5,8 @@ use std::fmt::Display;
 pub struct FileType {
     name: String,
     hl_opts: HighlightingOptions,
+    line_comment: Option<String>,
+    block_comment: Option<(String, String)>,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -15,6 +17,10 @@ pub struct HighlightingOptions {
     comments: bool,
     punctuation: bool,
     multiline_comments: bool,
+    // identifiers followed by `(` are function calls
+    function_calls: bool,
+    // arithmetic, comparison, logical and assignment operators get their own color
+    operators: bool,
     primary_keywords: Vec<String>,
     secondary_keywords: Vec<String>,
 }
@@ -24,6 +30,8 @@ impl Default for FileType {
         Self {
             name: String::from("No filetype"),
             hl_opts: HighlightingOptions::default(),
+            line_comment: None,
+            block_comment: None,
         }
     }
 }
@@ -35,6 +43,19 @@ impl FileType {
     pub fn highlighting_options(&self) -> &HighlightingOptions {
         &self.hl_opts
     }
+    /// The marker starting a comment running to the end of the line, if the language has one
+    pub fn line_comment(&self) -> Option<&str> {
+        self.line_comment.as_ref().map(|marker| marker.as_str())
+    }
+    /// The markers opening and closing a block comment, if the language has them
+    pub fn block_comment(&self) -> Option<(&str, &str)> {
+        self.block_comment
+            .as_ref()
+            .map(|(open, close)| (open.as_str(), close.as_str()))
+    }
+    pub fn is_markdown(&self) -> bool {
+        self.name == "Markdown"
+    }
     pub fn from(file_name: &str) -> Self {
         if file_name.ends_with(".rs") {
             return Self {
@@ -46,6 +67,8 @@ impl FileType {
                     comments: true,
                     punctuation: true,
                     multiline_comments: true,
+                    function_calls: true,
+                    operators: true,
                     primary_keywords: vec![
                         "as".to_string(),
                         "break".to_string(),
@@ -116,10 +139,100 @@ impl FileType {
                         "f64".to_string(),
                     ],
                 },
+                line_comment: Some("//".to_string()),
+                block_comment: Some(("/*".to_string(), "*/".to_string())),
+            };
+        }
+        if file_name.ends_with(".css") {
+            return Self {
+                name: String::from("CSS"),
+                hl_opts: HighlightingOptions {
+                    numbers: true,
+                    strings: true,
+                    punctuation: true,
+                    multiline_comments: true,
+                    ..HighlightingOptions::default()
+                },
+                line_comment: None,
+                block_comment: Some(("/*".to_string(), "*/".to_string())),
+            };
+        }
+        if file_name.ends_with(".md") || file_name.ends_with(".markdown") {
+            return Self {
+                name: String::from("Markdown"),
+                hl_opts: HighlightingOptions::default(),
+                line_comment: None,
+                block_comment: Some(("<!--".to_string(), "-->".to_string())),
+            };
+        }
+        if file_name.ends_with(".txt") {
+            return Self {
+                name: String::from("Text"),
+                hl_opts: HighlightingOptions::default(),
+                line_comment: None,
+                block_comment: None,
             };
         }
         Self::default()
     }
+
+    ///
+    /// Guess the files related to the given one (header <-> source, module <-> test)
+    /// Candidates are returned in order of preference, they may not exist on disk.
+    ///
+    pub fn related_files(file_name: &str) -> Vec<String> {
+        let path = std::path::Path::new(file_name);
+        let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem,
+            None => return Vec::new(),
+        };
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+        let parent = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+        let sibling = |name: String| parent.join(name).to_string_lossy().to_string();
+        let with_extensions = |extensions: &[&str]| -> Vec<String> {
+            extensions
+                .iter()
+                .map(|ext| sibling(format!("{}.{}", stem, ext)))
+                .collect()
+        };
+
+        match extension {
+            "h" => with_extensions(&["c", "cpp", "cc", "cxx"]),
+            "hpp" | "hh" | "hxx" => with_extensions(&["cpp", "cc", "cxx"]),
+            "c" => with_extensions(&["h"]),
+            "cpp" | "cc" | "cxx" => with_extensions(&["hpp", "h", "hh", "hxx"]),
+            "rs" => {
+                if stem == "mod" {
+                    // foo/mod.rs <-> foo.rs
+                    match parent.file_name().and_then(|name| name.to_str()) {
+                        Some(module) => {
+                            let grand_parent =
+                                parent.parent().unwrap_or_else(|| std::path::Path::new(""));
+                            vec![grand_parent
+                                .join(format!("{}.rs", module))
+                                .to_string_lossy()
+                                .to_string()]
+                        }
+                        None => Vec::new(),
+                    }
+                } else if stem.ends_with("_test") || stem.ends_with("_tests") {
+                    // foo_test.rs <-> foo.rs
+                    let module = stem.trim_end_matches("_tests").trim_end_matches("_test");
+                    vec![sibling(format!("{}.rs", module))]
+                } else {
+                    vec![
+                        sibling(format!("{}_test.rs", stem)),
+                        sibling(format!("{}_tests.rs", stem)),
+                        sibling(format!("{}/mod.rs", stem)),
+                    ]
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl Display for FileType {
@@ -153,4 +266,10 @@ impl HighlightingOptions {
     pub fn multiline_comments(&self) -> bool {
         self.multiline_comments
     }
+    pub fn function_calls(&self) -> bool {
+        self.function_calls
+    }
+    pub fn operators(&self) -> bool {
+        self.operators
+    }
 }
@@ -0,0 +1,141 @@
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum State {
+    Text,
+    // right after ESC
+    Escape,
+    // ESC [ (or the 8 bit CSI): parameters up to a final byte
+    ControlSequence,
+    // ESC ] (terminal title...): up to BEL or ESC \
+    OperatingSystemCommand,
+    OperatingSystemCommandEscape,
+}
+
+///
+/// Removes ANSI escape sequences (colors, cursor movement, titles) from text fed
+/// to it piece by piece, a sequence may be split between two pieces of a line
+/// but doesn't go on past `end_line`.
+///
+pub struct AnsiStripper {
+    state: State,
+    /// Number of complete sequences removed so far
+    pub stripped: usize,
+}
+
+impl Default for AnsiStripper {
+    fn default() -> Self {
+        Self {
+            state: State::Text,
+            stripped: 0,
+        }
+    }
+}
+
+impl AnsiStripper {
+    /// Return `text` without the escape sequences, remembering an unfinished one for the next piece
+    pub fn strip(&mut self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        for c in text.chars() {
+            self.state = match self.state {
+                State::Text => match c {
+                    '\x1b' => State::Escape,
+                    '\u{9b}' => State::ControlSequence,
+                    _ => {
+                        result.push(c);
+                        State::Text
+                    }
+                },
+                State::Escape => match c {
+                    '[' => State::ControlSequence,
+                    ']' => State::OperatingSystemCommand,
+                    _ => {
+                        // two characters sequences (ESC 7, ESC c, ...)
+                        self.stripped += 1;
+                        State::Text
+                    }
+                },
+                State::ControlSequence => match c {
+                    '\x40'..='\x7e' => {
+                        self.stripped += 1;
+                        State::Text
+                    }
+                    _ => State::ControlSequence,
+                },
+                State::OperatingSystemCommand => match c {
+                    '\x07' => {
+                        self.stripped += 1;
+                        State::Text
+                    }
+                    '\x1b' => State::OperatingSystemCommandEscape,
+                    _ => State::OperatingSystemCommand,
+                },
+                State::OperatingSystemCommandEscape => match c {
+                    '\\' => {
+                        self.stripped += 1;
+                        State::Text
+                    }
+                    _ => State::OperatingSystemCommand,
+                },
+            };
+        }
+        result
+    }
+
+    ///
+    /// End the line: an ESC left pending is returned to be kept as text, an unfinished
+    /// sequence (e.g. a title missing its terminator) is dropped up to the end of the line
+    /// and the next line starts as text.
+    ///
+    pub fn end_line(&mut self) -> &'static str {
+        let pending = match self.state {
+            State::Text => "",
+            State::Escape => "\x1b",
+            State::ControlSequence
+            | State::OperatingSystemCommand
+            | State::OperatingSystemCommandEscape => {
+                self.stripped += 1;
+                ""
+            }
+        };
+        self.state = State::Text;
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_lines(lines: &[&str]) -> (Vec<String>, usize) {
+        let mut stripper = AnsiStripper::default();
+        let lines = lines
+            .iter()
+            .map(|line| {
+                let mut stripped = stripper.strip(line);
+                stripped.push_str(stripper.end_line());
+                stripped
+            })
+            .collect();
+        (lines, stripper.stripped)
+    }
+
+    #[test]
+    fn strip_colors_and_titles() {
+        let (lines, stripped) = strip_lines(&["\x1b[1;31mred\x1b[0m", "\x1b]0;title\x07text"]);
+        assert_eq!(lines, ["red", "text"]);
+        assert_eq!(stripped, 3);
+    }
+
+    #[test]
+    fn lone_escape_at_end_of_line_is_kept() {
+        let (lines, stripped) = strip_lines(&["one\x1b", "two"]);
+        assert_eq!(lines, ["one\x1b", "two"]);
+        assert_eq!(stripped, 0);
+    }
+
+    #[test]
+    fn unterminated_title_ends_with_its_line() {
+        let (lines, stripped) = strip_lines(&["a\x1b]0;title", "b", "c\x1b[3"]);
+        assert_eq!(lines, ["a", "b", "c"]);
+        assert_eq!(stripped, 2);
+    }
+}
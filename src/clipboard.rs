@@ -0,0 +1,96 @@
+//!
+//! The system clipboard, through the command line tools of the platform
+//! (`pbcopy` on macOS, `clip` on Windows, `wl-copy` or `xclip` on Linux and BSDs).
+//!
+use crate::error::EditorError;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Put `text` on the system clipboard
+pub fn copy(text: &str) -> Result<(), EditorError> {
+    let command = match commands() {
+        Some((copy, _)) => copy,
+        None => return Ok(()),
+    };
+    let (program, arguments) = (command[0], &command[1..]);
+    let mut child = Command::new(program)
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        // xclip and wl-copy leave a process behind holding the clipboard, a pipe it inherits
+        // would only close once another program takes the selection
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|error| EditorError::io("run", program, error))?;
+    if let Some(stdin) = &mut child.stdin {
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|error| EditorError::io("write to", program, error))?;
+    }
+    // closing stdin lets the tool know the text is complete
+    drop(child.stdin.take());
+    let status = child
+        .wait()
+        .map_err(|error| EditorError::io("run", program, error))?;
+    if !status.success() {
+        return Err(EditorError::Command {
+            command: program.to_string(),
+            stderr: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The text on the system clipboard, `None` when there's no clipboard to read from
+pub fn paste() -> Result<Option<String>, EditorError> {
+    let command = match commands() {
+        Some((_, paste)) => paste,
+        None => return Ok(None),
+    };
+    let (program, arguments) = (command[0], &command[1..]);
+    let output = Command::new(program)
+        .args(arguments)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|error| EditorError::io("run", program, error))?;
+    if !output.status.success() {
+        return Err(EditorError::Command {
+            command: program.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    let text = String::from_utf8(output.stdout).map_err(|error| EditorError::Utf8 {
+        path: program.to_string(),
+        source: error,
+    })?;
+    // the editor works with `\n` only
+    Ok(Some(text.replace("\r\n", "\n")))
+}
+
+/// The commands copying to and pasting from the clipboard, `None` without a known clipboard
+#[cfg(target_os = "macos")]
+fn commands() -> Option<(&'static [&'static str], &'static [&'static str])> {
+    Some((&["pbcopy"], &["pbpaste"]))
+}
+
+#[cfg(windows)]
+fn commands() -> Option<(&'static [&'static str], &'static [&'static str])> {
+    Some((
+        &["clip"],
+        &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+    ))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn commands() -> Option<(&'static [&'static str], &'static [&'static str])> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        Some((&["wl-copy"], &["wl-paste", "--no-newline"]))
+    } else if std::env::var_os("DISPLAY").is_some() {
+        Some((
+            &["xclip", "-selection", "clipboard"],
+            &["xclip", "-selection", "clipboard", "-o"],
+        ))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,477 @@
+use crate::checksum;
+use crate::highlighting;
+use crate::terminal::{Color, KeyEvent};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+///
+/// Settings controlling the editor behavior.
+/// Every option has a default matching the editor's original behavior, size guardrails aside.
+///
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Ask before saving over a file that changed on disk since it was opened
+    pub confirm_external_overwrite: bool,
+    /// Flush saved files all the way to the disk (fsync), slower but crash safe
+    pub sync_on_save: bool,
+    /// Reload files changed on disk when they have no unsaved changes
+    pub auto_reload: bool,
+    /// Save a modified named buffer when switching away from it
+    pub auto_save_on_switch: bool,
+    /// Searching again past the last match continues from the other end of the document
+    pub search_wrap_around: bool,
+    /// Draw the status and message bars at the top of the screen instead of the bottom
+    pub status_bar_top: bool,
+    /// How many copied or cut texts are kept for pasting
+    pub yank_ring_size: usize,
+    /// Filetypes ("Text", "Markdown") whose paragraphs are reflowed to `hard_wrap_width` on save
+    pub hard_wrap_filetypes: Vec<String>,
+    pub hard_wrap_width: usize,
+    /// How long informational messages stay in the message bar, errors stay until the next key
+    pub status_message_timeout: Duration,
+    /// Show file names relative to the project root (the nearest directory with `.git`, `Cargo.toml`...)
+    pub relative_paths: bool,
+    /// Swapping lines moves the cursor along with its line (move line) instead of keeping it in place
+    pub swap_lines_follow_text: bool,
+    /// Ask before quitting even without unsaved changes, except from a single empty buffer
+    pub confirm_quit_always: bool,
+    /// How many Ctrl-Q presses in a row quit when there are unsaved changes
+    pub quit_times: usize,
+    /// Openers (`(`, `"`...) typing also inserts the closer for, per filetype name
+    pub auto_close_pairs: HashMap<String, Vec<char>>,
+    /// Algorithm of the buffer checksum command
+    pub checksum_algorithm: checksum::Algorithm,
+    /// Files larger than this many bytes are opened read-only
+    pub read_only_file_size: Option<u64>,
+    /// Files larger than this many bytes are not opened at all
+    pub max_file_size: Option<u64>,
+    /// Tab indents the line when the cursor is in its leading whitespace, and the lines of the
+    /// selection when there is one, inserting the indentation only in the middle of the text
+    pub smart_tab: bool,
+    /// Replace look-alike characters pasted into the terminal (non-breaking spaces, curly quotes,
+    /// dashes...) with their `paste_substitutions`
+    pub sanitize_paste: bool,
+    pub paste_substitutions: HashMap<char, String>,
+    /// Color the status bar background (red when modified), reverse video is used without.
+    /// Off when the `NO_COLOR` environment variable is set.
+    pub status_bar_colors: bool,
+    /// Formatter (program and arguments) each filetype's buffer is piped through before saving,
+    /// e.g. "Rust" to `["rustfmt"]`. The buffer is kept as is when the formatter fails.
+    pub format_commands: HashMap<String, Vec<String>>,
+    /// Mark the rows holding matches of the highlighted search along the right edge,
+    /// the whole document scaled to the screen height
+    pub search_match_ticks: bool,
+    /// Shade the bracket under the cursor along with its match (or alone in red without one)
+    pub match_brackets: bool,
+    /// Let the status bar take a second row when the file name and position don't fit on one
+    pub two_line_status: bool,
+    /// Backspace in space indentation goes back a whole indent level
+    pub smart_backspace: bool,
+    /// Highlight the whole document again once no edit happened for this long, correcting any
+    /// drift of the incremental highlighting. Done a chunk of rows at a time between keys.
+    pub idle_rehighlight: Option<Duration>,
+    /// Lines wider than this many columns are reported by the long line search
+    pub max_line_length: usize,
+    /// Range commands leaving the whole lines they transformed selected, when run on a selection:
+    /// "align", "comment", "number" and "wrap"
+    pub reselect_after: Vec<String>,
+    /// Copy and cut to the system clipboard too, and paste from it
+    pub system_clipboard: bool,
+    /// Start with the line number gutter shown (Ctrl-L toggles it)
+    pub line_numbers: bool,
+    /// Click to place the cursor and scroll with the wheel. Takes over the terminal's own
+    /// mouse selection.
+    pub mouse: bool,
+    /// Searches take the query as a regular expression (toggled with Alt-X)
+    pub regex_search: bool,
+    /// Columns a tab spans when the `.editorconfig` doesn't say
+    pub tab_width: usize,
+    /// Tab inserts spaces rather than a tab when the `.editorconfig` doesn't say
+    pub soft_tabs: bool,
+    /// Colors replacing the built in ones for some highlighting types
+    pub theme: HashMap<highlighting::Type, Color>,
+    /// Keys remapped to the key of an action, e.g. Ctrl-W to Ctrl-S to save with Ctrl-W
+    pub key_bindings: HashMap<KeyEvent, KeyEvent>,
+}
+
+impl Config {
+    ///
+    /// The defaults overridden by the user's config file, if there's one, along with
+    /// the problems found in it. Lines that can't be understood are skipped.
+    ///
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Config::default();
+        let path = match config_file() {
+            Some(path) if path.exists() => path,
+            _ => return (config, Vec::new()),
+        };
+        let warnings = match fs::read_to_string(&path) {
+            Ok(text) => config.parse(&text),
+            Err(error) => vec![format!("Could not read {}: {}", path.display(), error)],
+        };
+        (config, warnings)
+    }
+
+    ///
+    /// Apply `key = value` settings, one per line, `#` starting a comment.
+    /// Returns a warning for each line that could not be applied.
+    ///
+    pub fn parse(&mut self, text: &str) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.find('=') {
+                Some(index) => self.set(line[..index].trim(), line[index + 1..].trim()),
+                None => Err("expected key = value".to_string()),
+            };
+            if let Err(error) = result {
+                warnings.push(format!("config line {}: {}", number + 1, error));
+            }
+        }
+        warnings
+    }
+
+    /// Apply a single setting
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let flag = || match value {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" => Ok(false),
+            _ => Err(format!("{} is not true or false", value)),
+        };
+        if let Some(name) = key.strip_prefix("color.") {
+            let highlight_type =
+                parse_type(name).ok_or_else(|| format!("unknown highlighting type in {}", key))?;
+            let color = parse_color(value).ok_or_else(|| format!("unknown color {}", value))?;
+            self.theme.insert(highlight_type, color);
+            return Ok(());
+        }
+        if let Some(action) = key.strip_prefix("key.") {
+            let action = action_key(action).ok_or_else(|| format!("unknown action in {}", key))?;
+            let key = parse_key(value).ok_or_else(|| format!("unknown key {}", value))?;
+            self.key_bindings.insert(key, action);
+            return Ok(());
+        }
+        match key {
+            "tab_width" => {
+                self.tab_width = value
+                    .parse()
+                    .ok()
+                    .filter(|&width| width > 0)
+                    .ok_or_else(|| format!("{} is not a tab width", value))?
+            }
+            "soft_tabs" => self.soft_tabs = flag()?,
+            "theme" => match value {
+                "default" => self.theme.clear(),
+                "mono" => {
+                    self.theme = THEMED_TYPES
+                        .iter()
+                        .filter_map(|name| parse_type(name))
+                        .map(|highlight_type| (highlight_type, Color::Reset))
+                        .collect()
+                }
+                _ => return Err(format!("unknown theme {}", value)),
+            },
+            "line_numbers" => self.line_numbers = flag()?,
+            "mouse" => self.mouse = flag()?,
+            "match_brackets" => self.match_brackets = flag()?,
+            "quit_times" => {
+                self.quit_times = value
+                    .parse()
+                    .map_err(|_| format!("{} is not a number", value))?
+            }
+            "regex_search" => self.regex_search = flag()?,
+            "smart_tab" => self.smart_tab = flag()?,
+            "smart_backspace" => self.smart_backspace = flag()?,
+            "auto_reload" => self.auto_reload = flag()?,
+            "system_clipboard" => self.system_clipboard = flag()?,
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    /// The openers auto closed in files of the given filetype
+    pub fn auto_close_pairs_for(&self, filetype: &str) -> &[char] {
+        self.auto_close_pairs
+            .get(filetype)
+            .map_or(&[], |pairs| pairs.as_slice())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            confirm_external_overwrite: true,
+            sync_on_save: false,
+            auto_reload: false,
+            auto_save_on_switch: false,
+            search_wrap_around: false,
+            status_bar_top: false,
+            yank_ring_size: 16,
+            hard_wrap_filetypes: Vec::new(),
+            hard_wrap_width: 80,
+            status_message_timeout: Duration::from_secs(5),
+            relative_paths: false,
+            swap_lines_follow_text: false,
+            confirm_quit_always: false,
+            quit_times: 3,
+            auto_close_pairs: HashMap::new(),
+            checksum_algorithm: checksum::Algorithm::Crc32,
+            read_only_file_size: Some(256 * 1024 * 1024),
+            max_file_size: Some(2 * 1024 * 1024 * 1024),
+            smart_tab: false,
+            sanitize_paste: false,
+            paste_substitutions: default_paste_substitutions(),
+            status_bar_colors: std::env::var_os("NO_COLOR").is_none(),
+            format_commands: HashMap::new(),
+            search_match_ticks: false,
+            match_brackets: true,
+            two_line_status: false,
+            smart_backspace: false,
+            idle_rehighlight: Some(Duration::from_secs(30)),
+            max_line_length: 100,
+            reselect_after: ["align", "comment", "number", "wrap"]
+                .iter()
+                .map(|command| command.to_string())
+                .collect(),
+            system_clipboard: true,
+            line_numbers: false,
+            mouse: false,
+            regex_search: false,
+            tab_width: 4,
+            soft_tabs: false,
+            theme: HashMap::new(),
+            key_bindings: HashMap::new(),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/text_editor/config`, falling back to `~/.config`, or `%APPDATA%` on windows
+fn config_file() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    dir.map(|dir| dir.join("text_editor").join("config"))
+}
+
+/// The highlighting types a theme can color, as named in the config file
+const THEMED_TYPES: [&str; 13] = [
+    "number",
+    "match",
+    "string",
+    "character",
+    "comment",
+    "multiline_comment",
+    "keyword",
+    "secondary_keyword",
+    "function_call",
+    "operator",
+    "punctuation",
+    "selection",
+    "unmatched_bracket",
+];
+
+fn parse_type(name: &str) -> Option<highlighting::Type> {
+    use highlighting::Type;
+    Some(match name {
+        "number" => Type::Number,
+        "match" => Type::Match,
+        "string" => Type::String,
+        "character" => Type::Character,
+        "comment" => Type::Comment,
+        "multiline_comment" => Type::MultilineComment,
+        "keyword" => Type::PrimaryKeywords,
+        "secondary_keyword" => Type::SecondaryKeywords,
+        "function_call" => Type::FunctionCall,
+        "operator" => Type::Operator,
+        "punctuation" => Type::Punctuation,
+        "selection" => Type::Selection,
+        "unmatched_bracket" => Type::UnmatchedBracket,
+        _ => return None,
+    })
+}
+
+/// A color by name (`dark_cyan`, `grey`...) or as `#rrggbb`
+fn parse_color(value: &str) -> Option<Color> {
+    if value.starts_with('#') && value.len() == 7 {
+        let channel = |index: usize| u8::from_str_radix(value.get(index..index + 2)?, 16).ok();
+        return Some(Color::Rgb {
+            r: channel(1)?,
+            g: channel(3)?,
+            b: channel(5)?,
+        });
+    }
+    Some(match value {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "dark_grey" => Color::DarkGrey,
+        "red" => Color::Red,
+        "dark_red" => Color::DarkRed,
+        "green" => Color::Green,
+        "dark_green" => Color::DarkGreen,
+        "yellow" => Color::Yellow,
+        "dark_yellow" => Color::DarkYellow,
+        "blue" => Color::Blue,
+        "dark_blue" => Color::DarkBlue,
+        "magenta" => Color::Magenta,
+        "dark_magenta" => Color::DarkMagenta,
+        "cyan" => Color::Cyan,
+        "dark_cyan" => Color::DarkCyan,
+        "white" => Color::White,
+        "grey" => Color::Grey,
+        _ => return None,
+    })
+}
+
+/// The key triggering an action by default, the target of a remapped key
+fn action_key(action: &str) -> Option<KeyEvent> {
+    Some(KeyEvent::Ctrl(match action {
+        "save" => 'S',
+        "quit" => 'Q',
+        "find" => 'F',
+        "jump_back" => 'O',
+        "close" => 'W',
+        "next_buffer" => 'N',
+        "previous_buffer" => 'P',
+        "select_all" => 'A',
+        "copy" => 'C',
+        "cut" => 'X',
+        "paste" => 'V',
+        "undo" => 'Z',
+        "redo" => 'Y',
+        "goto_line" => 'G',
+        "line_numbers" => 'L',
+        _ => return None,
+    }))
+}
+
+/// A key as written in the config file: `ctrl-s`, `alt-x` or `f5`
+fn parse_key(value: &str) -> Option<KeyEvent> {
+    let value = value.to_lowercase();
+    let single = |rest: &str| {
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(rest) = value.strip_prefix("ctrl-") {
+        let c = single(rest)?;
+        if c.is_ascii_alphabetic() {
+            return Some(KeyEvent::Ctrl(c.to_ascii_uppercase()));
+        }
+    } else if let Some(rest) = value.strip_prefix("alt-") {
+        return single(rest).map(KeyEvent::Alt);
+    } else if let Some(rest) = value.strip_prefix('f') {
+        return rest.parse().ok().map(KeyEvent::F);
+    }
+    None
+}
+
+/// Typographic characters web pages are full of and their plain ASCII counterparts
+fn default_paste_substitutions() -> HashMap<char, String> {
+    let substitutions = [
+        ('\u{a0}', " "),
+        ('\u{2002}', " "),
+        ('\u{2003}', " "),
+        ('\u{2009}', " "),
+        ('\u{202f}', " "),
+        ('\u{200b}', ""),
+        ('\u{2018}', "'"),
+        ('\u{2019}', "'"),
+        ('\u{201c}', "\""),
+        ('\u{201d}', "\""),
+        ('\u{2013}', "-"),
+        ('\u{2014}', "-"),
+        ('\u{2212}', "-"),
+        ('\u{2026}', "..."),
+    ];
+    substitutions
+        .iter()
+        .map(|(from, to)| (*from, to.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid_settings() {
+        let mut config = Config::default();
+        let warnings = config.parse(
+            "# a comment\n\ntab_width = 2\nsoft_tabs = yes\ncolor.comment = #00ff80\nkey.save = ctrl-w\n",
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(config.tab_width, 2);
+        assert!(config.soft_tabs);
+        assert_eq!(
+            config.theme.get(&highlighting::Type::Comment),
+            Some(&Color::Rgb {
+                r: 0,
+                g: 255,
+                b: 128
+            })
+        );
+        assert_eq!(
+            config.key_bindings.get(&KeyEvent::Ctrl('W')),
+            Some(&KeyEvent::Ctrl('S'))
+        );
+    }
+
+    #[test]
+    fn warn_about_each_bad_line() {
+        let warning = |line: &str| {
+            let mut warnings = Config::default().parse(line);
+            assert_eq!(warnings.len(), 1, "{}", line);
+            warnings.remove(0)
+        };
+        assert_eq!(warning("tab_width"), "config line 1: expected key = value");
+        assert_eq!(
+            warning("color.nothing = red"),
+            "config line 1: unknown highlighting type in color.nothing"
+        );
+        assert_eq!(
+            warning("color.comment = pink"),
+            "config line 1: unknown color pink"
+        );
+        assert_eq!(
+            warning("key.dance = ctrl-d"),
+            "config line 1: unknown action in key.dance"
+        );
+        assert_eq!(
+            warning("key.save = ctrl-1"),
+            "config line 1: unknown key ctrl-1"
+        );
+        assert_eq!(
+            warning("tab_width = 0"),
+            "config line 1: 0 is not a tab width"
+        );
+        assert_eq!(
+            warning("mouse = maybe"),
+            "config line 1: maybe is not true or false"
+        );
+        assert_eq!(warning("theme = neon"), "config line 1: unknown theme neon");
+        assert_eq!(
+            warning("quit_times = many"),
+            "config line 1: many is not a number"
+        );
+        assert_eq!(
+            warning("colour = red"),
+            "config line 1: unknown setting colour"
+        );
+        // the line numbers count comments and blank lines
+        assert_eq!(
+            Config::default().parse("# settings\n\nmouse = on\nwrong"),
+            vec!["config line 4: expected key = value".to_string()]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+///
+/// A merge conflict left by git in a file:
+///
+/// ```text
+/// <<<<<<< ours
+/// our lines
+/// ||||||| base (diff3 style only)
+/// common ancestor lines
+/// =======
+/// their lines
+/// >>>>>>> theirs
+/// ```
+///
+/// Fields are the rows of the marker lines.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub start: usize,
+    pub base: Option<usize>,
+    pub separator: usize,
+    pub end: usize,
+}
+
+/// Which side of a conflict to keep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+}
+
+impl Conflict {
+    /// The rows of our side, without the markers
+    pub fn ours(&self) -> std::ops::Range<usize> {
+        self.start + 1..self.base.unwrap_or(self.separator)
+    }
+
+    /// The rows of their side, without the markers
+    pub fn theirs(&self) -> std::ops::Range<usize> {
+        self.separator + 1..self.end
+    }
+
+    pub fn contains(&self, row: usize) -> bool {
+        (self.start..=self.end).contains(&row)
+    }
+
+    /// The rows kept when resolving the conflict the given way
+    pub fn kept(&self, resolution: Resolution) -> Vec<usize> {
+        match resolution {
+            Resolution::Ours => self.ours().collect(),
+            Resolution::Theirs => self.theirs().collect(),
+            Resolution::Both => self.ours().chain(self.theirs()).collect(),
+        }
+    }
+}
+
+///
+/// Find the complete conflicts in the given lines, in order.
+/// Markers out of place (a separator outside of a conflict, a conflict never closed) are ignored.
+///
+pub fn find_conflicts<'a, I>(lines: I) -> Vec<Conflict>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut conflicts = Vec::new();
+    let mut start = None;
+    let mut base = None;
+    let mut separator = None;
+    for (row, line) in lines.into_iter().enumerate() {
+        if is_marker(line, '<') {
+            start = Some(row);
+            base = None;
+            separator = None;
+        } else if is_marker(line, '|') && start.is_some() && separator.is_none() {
+            base = Some(row);
+        } else if is_marker(line, '=') && start.is_some() {
+            separator = Some(row);
+        } else if is_marker(line, '>') {
+            if let (Some(start), Some(separator)) = (start, separator) {
+                conflicts.push(Conflict {
+                    start,
+                    base,
+                    separator,
+                    end: row,
+                });
+            }
+            start = None;
+            separator = None;
+        }
+    }
+    conflicts
+}
+
+/// Whether the line is a conflict marker: seven times the character, then nothing or a space
+fn is_marker(line: &str, marker: char) -> bool {
+    let mut chars = line.chars();
+    if !chars.by_ref().take(7).all(|c| c == marker) || line.chars().count() < 7 {
+        return false;
+    }
+    match chars.next() {
+        None => true,
+        Some(' ') => marker != '=',
+        Some(_) => false,
+    }
+}
@@ -0,0 +1,97 @@
+///
+/// How a line of the buffer compares to the file on disk
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LineChange {
+    Unchanged,
+    Added,
+    Changed,
+}
+
+/// Line by line comparison of a buffer against its saved version
+pub struct Diff {
+    /// One entry per line of the new text
+    pub lines: Vec<LineChange>,
+    /// Lines of the old text that are gone without a replacement
+    pub removed: usize,
+}
+
+/// Past this many cells the LCS table is too big, the differing block is just marked changed
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+///
+/// Compare `old` and `new` line by line (longest common subsequence).
+/// Added lines next to removed ones are reported as changed.
+///
+pub fn diff_lines(old: &[&str], new: &[&str]) -> Diff {
+    // the common prefix and suffix are unchanged, only diff what's between
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut lines = vec![LineChange::Unchanged; prefix];
+    let removed;
+    if old_middle.len() * new_middle.len() > MAX_LCS_CELLS {
+        let changed = new_middle.len().min(old_middle.len());
+        lines.extend(vec![LineChange::Changed; changed]);
+        lines.extend(vec![LineChange::Added; new_middle.len() - changed]);
+        removed = old_middle.len() - changed;
+    } else {
+        let (middle, middle_removed) = diff_middle(old_middle, new_middle);
+        lines.extend(middle);
+        removed = middle_removed;
+    }
+    lines.extend(vec![LineChange::Unchanged; suffix]);
+    Diff { lines, removed }
+}
+
+fn diff_middle(old: &[&str], new: &[&str]) -> (Vec<LineChange>, usize) {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i][j] = length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::with_capacity(m);
+    let mut removed = 0;
+    // deletions not yet paired with an insertion, they turn the next insertions into changes
+    let mut pending_deletes = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            removed += pending_deletes;
+            pending_deletes = 0;
+            lines.push(LineChange::Unchanged);
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] > lcs[i + 1][j]) {
+            if pending_deletes > 0 {
+                pending_deletes -= 1;
+                lines.push(LineChange::Changed);
+            } else {
+                lines.push(LineChange::Added);
+            }
+            j += 1;
+        } else {
+            pending_deletes += 1;
+            i += 1;
+        }
+    }
+    (lines, removed + pending_deletes)
+}
@@ -1,18 +1,90 @@
+use crate::ansi::AnsiStripper;
+use crate::conflict::{self, Conflict, Resolution};
+use crate::diff;
 use crate::editor::Position;
+use crate::editorconfig::{EditorConfig, EndOfLine, IndentStyle};
+use crate::error::EditorError;
 use crate::filetype::FileType;
+use crate::highlighting;
+use crate::highlighting::{Highlighter, TokenHighlighter};
+use crate::pattern::Pattern;
 use crate::row::Row;
+use crate::undo::{Edit, History, Step};
+use crate::wrap;
 use std::clone::Clone;
+use std::collections::hash_map::DefaultHasher;
 use std::fs;
+use std::hash::Hasher;
+use std::io::BufWriter;
 use std::io::Write;
+use std::ops::Range;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::time::SystemTime;
 use std::usize;
 
-#[derive(Default, Debug)]
+/// How many rows away the bracket matching the one under the cursor is looked for
+const MAX_BRACKET_ROWS: usize = 5000;
+
 pub struct Document {
     pub rows: Vec<Row>,
     pub filetype: FileType,
     pub filename: Option<String>,
-    pub search_string: Option<String>,
+    pub search_string: Option<Pattern>,
+    // query of the last search made in this document, for searching again
+    last_search: Option<Pattern>,
     is_dirty: bool,
+    // bumped by every change to the text
+    revision: u64,
+    // what the file looked like on disk when we last read or wrote it
+    disk_hash: Option<u64>,
+    disk_modified: Option<SystemTime>,
+    /// Settings from the project's `.editorconfig` files
+    pub editorconfig: EditorConfig,
+    highlighter: Rc<dyn Highlighter>,
+    // opened too large to edit safely, edits and saves are refused
+    read_only: bool,
+    // how the file ended its lines (the most common ending) and whether its last line was ended,
+    // kept when saving unless the editorconfig says otherwise
+    file_line_ending: EndOfLine,
+    final_newline: bool,
+    // merge conflicts left in the file, only tracked (and shaded) when it had some on open
+    conflicts: Vec<Conflict>,
+    // nesting of the batches in progress and the first row they edited,
+    // highlighting waits for the outermost one to end
+    batch_depth: usize,
+    batch_first: Option<usize>,
+    history: History,
+    // the user's indentation, for what the editorconfig leaves unsaid
+    default_tab_width: usize,
+    soft_tabs: bool,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            rows: Vec::new(),
+            filetype: FileType::default(),
+            filename: None,
+            search_string: None,
+            last_search: None,
+            is_dirty: false,
+            revision: 0,
+            disk_hash: None,
+            disk_modified: None,
+            editorconfig: EditorConfig::default(),
+            highlighter: Rc::new(TokenHighlighter::new(FileType::default())),
+            read_only: false,
+            file_line_ending: EndOfLine::Lf,
+            final_newline: true,
+            conflicts: Vec::new(),
+            batch_depth: 0,
+            batch_first: None,
+            history: History::default(),
+            default_tab_width: 4,
+            soft_tabs: false,
+        }
+    }
 }
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -20,27 +92,159 @@ pub enum SearchDirection {
     Backward,
 }
 
-impl Document {
-    pub fn open(filename: &str) -> Result<Self, std::io::Error> {
+///
+/// How to open a document, for embedders needing more than `Document::open`.
+/// Start from `Document::options()`, set what matters and finish with `open(filename)`.
+///
+#[derive(Clone)]
+pub struct OpenOptions {
+    read_only: bool,
+    read_only_size: Option<u64>,
+    max_size: Option<u64>,
+    detect_binary: bool,
+    line_ending: Option<EndOfLine>,
+    highlighter: Option<Rc<dyn Highlighter>>,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            read_only_size: None,
+            max_size: None,
+            detect_binary: true,
+            line_ending: None,
+            highlighter: None,
+        }
+    }
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse edits and saves whatever the size of the file
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Open files larger than `size` bytes read-only, `None` for no limit
+    pub fn read_only_above(mut self, size: Option<u64>) -> Self {
+        self.read_only_size = size;
+        self
+    }
+
+    /// Refuse files larger than `size` bytes, `None` for no limit
+    pub fn max_size(mut self, size: Option<u64>) -> Self {
+        self.max_size = size;
+        self
+    }
+
+    /// Refuse files that look binary (the default), or load them as long as they are UTF-8
+    pub fn detect_binary(mut self, detect_binary: bool) -> Self {
+        self.detect_binary = detect_binary;
+        self
+    }
+
+    /// Line ending used when saving, overriding `.editorconfig` and the file's own
+    pub fn line_ending(mut self, line_ending: EndOfLine) -> Self {
+        self.line_ending = Some(line_ending);
+        self
+    }
+
+    /// Highlight with `highlighter` rather than the built in one for the filetype
+    pub fn highlighter(mut self, highlighter: Rc<dyn Highlighter>) -> Self {
+        self.highlighter = Some(highlighter);
+        self
+    }
+
+    ///
+    /// Open `filename` with these options.
+    /// The size is checked before reading anything so a huge file can't exhaust the memory.
+    ///
+    pub fn open(&self, filename: &str) -> Result<Document, EditorError> {
+        let size = fs::metadata(filename)
+            .map_err(|error| EditorError::io("read", filename, error))?
+            .len();
+        if let Some(limit) = self.max_size {
+            if size > limit {
+                return Err(EditorError::TooLarge {
+                    path: filename.to_string(),
+                    size,
+                    limit,
+                });
+            }
+        }
+        let read_only = self.read_only || self.read_only_size.map_or(false, |limit| size > limit);
         let mut rows = Vec::<Row>::new();
         let is_dirty = false;
         let filetype = FileType::from(&filename);
-        let contents = fs::read_to_string(filename)?;
+        let bytes = fs::read(filename).map_err(|error| EditorError::io("read", filename, error))?;
+        if self.detect_binary && looks_binary(&bytes) {
+            return Err(EditorError::Binary {
+                path: filename.to_string(),
+            });
+        }
+        let contents = String::from_utf8(bytes).map_err(|error| EditorError::Utf8 {
+            path: filename.to_string(),
+            source: error,
+        })?;
+        let disk_hash = Some(content_hash(contents.as_bytes()));
+        let disk_modified = modified_time(filename);
         let search_string = Option::None;
-        contents.lines().for_each(|line| {
-            let mut row = Row::from(line);
-            row.highlight(&filetype, &search_string);
-            rows.push(row);
+        let (lines, line_ending, final_newline) = split_lines(&contents);
+        lines.iter().for_each(|line| {
+            rows.push(Row::from(*line));
         });
 
+        let mut editorconfig = EditorConfig::for_file(filename);
+        if self.line_ending.is_some() {
+            editorconfig.end_of_line = self.line_ending;
+        }
         let filename = Some(filename.to_string());
-        Ok(Self {
+        let highlighter = match &self.highlighter {
+            Some(highlighter) => Rc::clone(highlighter),
+            None => Rc::new(TokenHighlighter::new(filetype.clone())),
+        };
+        let mut document = Document {
             rows,
             is_dirty,
+            revision: 0,
             search_string,
+            last_search: None,
             filename,
             filetype,
-        })
+            disk_hash,
+            disk_modified,
+            editorconfig,
+            highlighter,
+            read_only,
+            file_line_ending: line_ending.unwrap_or(EndOfLine::Lf),
+            final_newline,
+            conflicts: Vec::new(),
+            batch_depth: 0,
+            batch_first: None,
+            history: History::default(),
+            default_tab_width: 4,
+            soft_tabs: false,
+        };
+        document.conflicts = conflict::find_conflicts(lines.iter().cloned());
+        document.hightlight();
+        Ok(document)
+    }
+}
+
+impl Document {
+    /// Open a file with the default options
+    pub fn open(filename: &str) -> Result<Self, EditorError> {
+        OpenOptions::new().open(filename)
+    }
+
+    /// Options to open a file with, see `OpenOptions`
+    pub fn options() -> OpenOptions {
+        OpenOptions::new()
     }
 
     pub fn row(&self, index: usize) -> Option<&Row> {
@@ -57,80 +261,1231 @@ impl Document {
     pub fn len(&self) -> usize {
         self.rows.len()
     }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    /// Refuse (or allow again) edits and saves
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
     pub fn is_dirty(&self) -> bool {
         self.is_dirty
     }
+
+    /// Flag the document as modified (or not), e.g. after saving it by other means
+    pub fn set_dirty(&mut self, dirty: bool) {
+        self.is_dirty = dirty;
+    }
+
+    /// Forget about the unsaved changes, saving will not write the document until it changes again
+    pub fn mark_clean(&mut self) {
+        self.set_dirty(false);
+    }
+
+    ///
+    /// A number bumped by every change to the text, including reloads, so an embedder can tell
+    /// the document changed since it last looked without comparing the text.
+    /// Changes made directly to `rows` are not counted.
+    ///
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    fn changed(&mut self) {
+        self.is_dirty = true;
+        self.revision += 1;
+    }
     pub fn delete(&mut self, pos: &Position) {
         let doc_len = self.len();
         if pos.y >= doc_len {
             return;
         }
         if pos.x == self.rows[pos.y].len() && pos.y + 1 < doc_len {
+            let x = self.rows[pos.y].text().chars().count();
+            self.history.record(Edit::Join(Position { x, y: pos.y }));
             let next_row = self.rows.remove(pos.y + 1);
             let this_row = &mut self.rows[pos.y];
             this_row.append(&next_row);
         } else {
+            let c = match self.rows[pos.y].text().chars().nth(pos.x) {
+                Some(c) => c,
+                None => return,
+            };
+            self.history.record(Edit::Delete(pos.clone(), c));
             let row = &mut self.rows[pos.y];
             row.delete(pos.x);
         }
-        self.is_dirty = true;
+        self.highlight_rows(pos.y, pos.y);
+        self.changed();
     }
+    ///
+    /// Insert a character at the given position, a new line splits the row.
+    /// Inserting at `pos.y == self.len()` (the line past the end, where the cursor sits
+    /// in an empty document) first appends an empty row to insert into, a single undo
+    /// step removing both.
+    ///
     pub fn insert(&mut self, c: char, pos: &Position) {
         let doc_len = self.len();
         if pos.y > doc_len {
             return;
         }
+        if pos.y == doc_len {
+            // the row appended and what's typed in it are undone together
+            return self.batch(|document| {
+                document.history.record(Edit::Rows {
+                    first: doc_len,
+                    before: Vec::new(),
+                    after: vec![String::new()],
+                });
+                document.rows.push(Row::default());
+                document.insert(c, pos);
+            });
+        }
         if c == '\n' {
+            self.history.record(Edit::Split(pos.clone()));
             if pos.x == 0 {
                 self.rows.insert(pos.y, Row::default());
             } else {
-                let row = &mut self.rows[pos.y];
-                row.highlight(&self.filetype, &self.search_string);
-                let mut new_row = row.split(pos.x);
-                new_row.highlight(&self.filetype, &self.search_string);
+                let new_row = self.rows[pos.y].split(pos.x);
                 self.rows.insert(pos.y + 1, new_row);
             }
-        } else if pos.y == self.rows.len() {
-            let mut row = Row::default();
-            row.insert(c, 0);
-            row.highlight(&self.filetype, &self.search_string);
-            self.rows.push(row);
+            self.highlight_rows(pos.y, pos.y + 1);
         } else {
-            let row = &mut self.rows[pos.y];
-            row.highlight(&self.filetype, &self.search_string);
-            row.insert(c, pos.x);
+            self.history.record(Edit::Insert(pos.clone(), c));
+            self.rows[pos.y].insert(c, pos.x);
+            self.highlight_rows(pos.y, pos.y);
         }
-        self.is_dirty = true;
+        self.changed();
+    }
+
+    ///
+    /// Revert the last step of edits (a typed word, a paste, a command...).
+    /// Returns where the step started, `None` when there's nothing to undo.
+    ///
+    pub fn undo(&mut self) -> Option<Position> {
+        let step = self.history.take_undo()?;
+        for edit in step.edits.iter().rev() {
+            self.revert(edit);
+        }
+        let start = step.edits.first().map(Edit::start);
+        self.highlight_step(&step);
+        self.history.undone(step);
+        self.changed();
+        start
+    }
+
+    ///
+    /// Make the last undone step again.
+    /// Returns where the step ended, `None` when there's nothing to redo.
+    ///
+    pub fn redo(&mut self) -> Option<Position> {
+        let step = self.history.take_redo()?;
+        for edit in &step.edits {
+            self.apply(edit);
+        }
+        let end = step.edits.last().map(Edit::end);
+        self.highlight_step(&step);
+        self.history.redone(step);
+        self.changed();
+        end
+    }
+
+    /// Highlight the rows an undone or redone step went over, rather than the whole document
+    fn highlight_step(&mut self, step: &Step) {
+        if self.is_empty() {
+            return;
+        }
+        let first = step.edits.iter().map(|edit| edit.start().y).min();
+        let last = step.edits.iter().map(Edit::last_row).max();
+        if let (Some(first), Some(last)) = (first, last) {
+            let last = last.min(self.len() - 1);
+            self.highlight_rows(first.min(last), last);
+        }
+    }
+
+    /// Make an edit from the history (again), without recording it
+    fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert(pos, c) => self.rows[pos.y].insert(*c, pos.x),
+            Edit::Delete(pos, _) => self.rows[pos.y].delete(pos.x),
+            Edit::Split(pos) => {
+                let new_row = self.rows[pos.y].split(pos.x);
+                self.rows.insert(pos.y + 1, new_row);
+            }
+            Edit::Join(pos) => {
+                let next_row = self.rows.remove(pos.y + 1);
+                self.rows[pos.y].append(&next_row);
+            }
+            Edit::Rows {
+                first,
+                before,
+                after,
+            } => {
+                let rows = after.iter().map(|line| Row::from(&line[..]));
+                self.rows.splice(*first..*first + before.len(), rows);
+            }
+        }
+    }
+
+    /// Reverse an edit from the history, without recording it
+    fn revert(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert(pos, c) => self.apply(&Edit::Delete(pos.clone(), *c)),
+            Edit::Delete(pos, c) => self.apply(&Edit::Insert(pos.clone(), *c)),
+            Edit::Split(pos) => self.apply(&Edit::Join(pos.clone())),
+            Edit::Join(pos) => self.apply(&Edit::Split(pos.clone())),
+            Edit::Rows {
+                first,
+                before,
+                after,
+            } => self.apply(&Edit::Rows {
+                first: *first,
+                before: after.clone(),
+                after: before.clone(),
+            }),
+        }
+    }
+
+    /// The text of the rows in `range`
+    fn lines(&self, range: Range<usize>) -> Vec<String> {
+        self.rows[range]
+            .iter()
+            .map(|row| row.text().clone())
+            .collect()
+    }
+
+    /// Record that the rows from `first` that read `before` are now the `count` rows from `first`
+    fn record_rows(&mut self, first: usize, before: Vec<String>, count: usize) {
+        let after = self.lines(first..first + count);
+        self.history.record(Edit::Rows {
+            first,
+            before,
+            after,
+        });
+    }
+
+    ///
+    /// Remove the ANSI escape sequences (e.g. colors from pasted terminal output) from every row.
+    /// Returns how many sequences were removed.
+    ///
+    pub fn strip_ansi(&mut self) -> usize {
+        let mut stripper = AnsiStripper::default();
+        let before = self.lines(0..self.len());
+        for row in self.rows.iter_mut() {
+            let mut stripped = stripper.strip(row.text());
+            stripped.push_str(stripper.end_line());
+            if stripped != *row.text() {
+                *row = Row::from(&stripped[..]);
+            }
+        }
+        if stripper.stripped > 0 {
+            self.record_rows(0, before, self.len());
+            self.hightlight();
+            self.changed();
+        }
+        stripper.stripped
+    }
+
+    ///
+    /// Reflow the paragraphs to at most `width` columns. Returns how many lines were reformatted.
+    ///
+    pub fn hard_wrap(&mut self, width: usize) -> usize {
+        let lines: Vec<&str> = self.rows.iter().map(|row| row.text().as_str()).collect();
+        let (wrapped, reformatted) = wrap::hard_wrap(&lines, width, self.filetype.is_markdown());
+        if reformatted > 0 {
+            let before = self.lines(0..self.len());
+            self.rows = wrapped.iter().map(|line| Row::from(&line[..])).collect();
+            self.record_rows(0, before, self.len());
+            self.hightlight();
+            self.changed();
+        }
+        reformatted
+    }
+
+    ///
+    /// Replace the text with what `command` outputs when fed the text on its standard input
+    /// (a formatter). The text is left alone unless the command succeeds.
+    /// Returns whether the text changed.
+    ///
+    pub fn pipe_through(&mut self, command: &[String]) -> Result<bool, EditorError> {
+        let (program, arguments) = match command.split_first() {
+            Some(split) => split,
+            None => return Ok(false),
+        };
+        let mut child = Command::new(program)
+            .args(arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| EditorError::io("run", program, error))?;
+        let mut input = String::new();
+        for row in &self.rows {
+            input.push_str(row.text());
+            input.push('\n');
+        }
+        // written from another thread, the command may fill its output before reading all of it
+        let mut stdin = child.stdin.take();
+        let writer = std::thread::spawn(move || {
+            if let Some(stdin) = &mut stdin {
+                let _ = stdin.write_all(input.as_bytes());
+            }
+        });
+        let output = child
+            .wait_with_output()
+            .map_err(|error| EditorError::io("run", program, error))?;
+        let _ = writer.join();
+        if !output.status.success() {
+            return Err(EditorError::Command {
+                command: program.clone(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+        let formatted = String::from_utf8(output.stdout).map_err(|error| EditorError::Utf8 {
+            path: program.clone(),
+            source: error,
+        })?;
+        let rows: Vec<Row> = formatted.lines().map(Row::from).collect();
+        let changed = rows.len() != self.rows.len()
+            || rows
+                .iter()
+                .zip(&self.rows)
+                .any(|(new, old)| new.text() != old.text());
+        if changed {
+            let before = self.lines(0..self.len());
+            self.rows = rows;
+            self.record_rows(0, before, self.len());
+            self.hightlight();
+            self.changed();
+        }
+        Ok(changed)
     }
 
-    pub fn save(&mut self) -> std::result::Result<(), std::io::Error> {
-        if self.is_dirty() {
-            if let Some(filename) = &self.filename {
-                let filepath = std::path::Path::new(&filename[..]);
-                let mut file = if filepath.exists() {
-                    fs::File::create(&filepath)?
+    ///
+    /// Switch the case of the character at `pos`, which may change the row's length (`ß` becomes `SS`).
+    /// Returns how many characters replaced it, `None` past the end of the row.
+    ///
+    pub fn toggle_case(&mut self, pos: &Position) -> Option<usize> {
+        let before = vec![self.rows.get(pos.y)?.text().clone()];
+        let row = self.rows.get_mut(pos.y)?;
+        let c = row.text().chars().nth(pos.x)?;
+        let toggled: String = if c.is_lowercase() {
+            c.to_uppercase().collect()
+        } else if c.is_uppercase() {
+            c.to_lowercase().collect()
+        } else {
+            return Some(1);
+        };
+        row.delete(pos.x);
+        for (offset, replacement) in toggled.chars().enumerate() {
+            row.insert(replacement, pos.x + offset);
+        }
+        self.record_rows(pos.y, before, 1);
+        self.highlight_rows(pos.y, pos.y);
+        self.changed();
+        Some(toggled.chars().count())
+    }
+
+    /// Exchange the text of two rows, does nothing when either is out of the document
+    pub fn swap_rows(&mut self, a: usize, b: usize) -> bool {
+        if a >= self.len() || b >= self.len() {
+            return false;
+        }
+        let (first, last) = (a.min(b), a.max(b));
+        let before = self.lines(first..last + 1);
+        self.rows.swap(a, b);
+        self.record_rows(first, before, last + 1 - first);
+        self.highlight_rows(a.min(b), a.max(b));
+        self.changed();
+        true
+    }
+
+    /// Remove the blank lines (empty or only whitespace) at the top, returns the removed rows
+    pub fn remove_leading_blank_lines(&mut self) -> Vec<usize> {
+        let removed: Vec<usize> = (0..self.len()).take_while(|&y| self.is_blank(y)).collect();
+        self.remove_rows(&removed);
+        removed
+    }
+
+    /// Remove the blank lines at the bottom, returns the removed rows
+    pub fn remove_trailing_blank_lines(&mut self) -> Vec<usize> {
+        let mut removed: Vec<usize> = (0..self.len())
+            .rev()
+            .take_while(|&y| self.is_blank(y))
+            .collect();
+        removed.reverse();
+        self.remove_rows(&removed);
+        removed
+    }
+
+    /// Collapse every run of blank lines to its first line, returns the removed rows
+    pub fn squeeze_blank_lines(&mut self) -> Vec<usize> {
+        let removed: Vec<usize> = (1..self.len())
+            .filter(|&y| self.is_blank(y) && self.is_blank(y - 1))
+            .collect();
+        self.remove_rows(&removed);
+        removed
+    }
+
+    fn is_blank(&self, y: usize) -> bool {
+        self.rows[y].text().trim().is_empty()
+    }
+
+    /// Delete from `at` to the end of its line, the line ending staying
+    pub fn delete_to_eol(&mut self, at: &Position) {
+        let len = match self.rows.get(at.y) {
+            Some(row) if at.x < row.len() => row.len(),
+            _ => return,
+        };
+        self.delete_range(at, &Position { x: len, y: at.y });
+    }
+
+    /// Delete the row `y` along with its line ending, the following rows moving up
+    pub fn delete_line(&mut self, y: usize) {
+        if y >= self.len() {
+            return;
+        }
+        let before = self.lines(y..y + 1);
+        self.rows.remove(y);
+        self.record_rows(y, before, 0);
+        // the row moving up may start in another state (e.g. in a comment) than it did
+        if y < self.len() {
+            self.highlight_rows(y, y);
+        }
+        self.changed();
+    }
+
+    /// Remove the rows at the given (ascending) indices
+    fn remove_rows(&mut self, removed: &[usize]) {
+        if removed.is_empty() {
+            return;
+        }
+        let before = self.lines(0..self.len());
+        let mut index = 0;
+        let mut removed = removed.iter().peekable();
+        self.rows.retain(|_| {
+            let keep = removed.peek() != Some(&&index);
+            if !keep {
+                removed.next();
+            }
+            index += 1;
+            keep
+        });
+        self.record_rows(0, before, self.len());
+        self.hightlight();
+        self.changed();
+    }
+
+    ///
+    /// Where the bracket matching the one at `at` is, skipping the pairs nested in between and
+    /// the brackets in strings and comments. `None` when `at` is not on a bracket in code,
+    /// `Some(None)` when nothing matches it within `MAX_BRACKET_ROWS` rows.
+    ///
+    pub fn matching_bracket(&self, at: &Position) -> Option<Option<Position>> {
+        let in_code = |row: &Row, x: usize| match row.highlighting().get(x) {
+            Some(highlighting::Type::String)
+            | Some(highlighting::Type::Character)
+            | Some(highlighting::Type::Comment)
+            | Some(highlighting::Type::MultilineComment) => false,
+            _ => true,
+        };
+        let row = self.rows.get(at.y)?;
+        let bracket = row.text().chars().nth(at.x)?;
+        let (open, close, forward) = match bracket {
+            '(' => ('(', ')', true),
+            '[' => ('[', ']', true),
+            '{' => ('{', '}', true),
+            ')' => (')', '(', false),
+            ']' => (']', '[', false),
+            '}' => ('}', '{', false),
+            _ => return None,
+        };
+        if !in_code(row, at.x) {
+            return None;
+        }
+        let mut depth = 0;
+        let rows: Box<dyn Iterator<Item = usize>> = if forward {
+            Box::new(at.y..self.len().min(at.y + MAX_BRACKET_ROWS))
+        } else {
+            Box::new((at.y.saturating_sub(MAX_BRACKET_ROWS)..=at.y).rev())
+        };
+        for y in rows {
+            let row = &self.rows[y];
+            let chars: Vec<char> = row.text().chars().collect();
+            let columns: Box<dyn Iterator<Item = usize>> = match (forward, y == at.y) {
+                (true, true) => Box::new(at.x..chars.len()),
+                (true, false) => Box::new(0..chars.len()),
+                (false, true) => Box::new((0..=at.x).rev()),
+                (false, false) => Box::new((0..chars.len()).rev()),
+            };
+            for x in columns {
+                if chars[x] == open && in_code(row, x) {
+                    depth += 1;
+                } else if chars[x] == close && in_code(row, x) {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(Some(Position { x, y }));
+                    }
+                }
+            }
+        }
+        Some(None)
+    }
+
+    /// Offset of the position in the saved file, in bytes
+    pub fn byte_offset(&self, pos: &Position) -> usize {
+        let line_ending = self.line_ending().len();
+        let before: usize = self
+            .rows
+            .iter()
+            .take(pos.y)
+            .map(|row| row.text().len() + line_ending)
+            .sum();
+        let in_row = self.rows.get(pos.y).map_or(0, |row| {
+            row.text().chars().take(pos.x).map(|c| c.len_utf8()).sum()
+        });
+        before + in_row
+    }
+
+    /// The line ending used when saving: the editorconfig's, else the one found in the file
+    pub fn end_of_line(&self) -> EndOfLine {
+        self.editorconfig
+            .end_of_line
+            .unwrap_or(self.file_line_ending)
+    }
+
+    ///
+    /// Save with the given line ending from now on, whatever the file or its editorconfig used.
+    /// Returns false when it was the line ending already.
+    ///
+    pub fn set_end_of_line(&mut self, end_of_line: EndOfLine) -> bool {
+        if self.end_of_line() == end_of_line {
+            return false;
+        }
+        self.file_line_ending = end_of_line;
+        if self.editorconfig.end_of_line.is_some() {
+            self.editorconfig.end_of_line = Some(end_of_line);
+        }
+        self.changed();
+        true
+    }
+
+    /// The bytes ending each line when saving
+    fn line_ending(&self) -> &'static [u8] {
+        match self.end_of_line() {
+            EndOfLine::CrLf => b"\r\n",
+            EndOfLine::Cr => b"\r",
+            EndOfLine::Lf => b"\n",
+        }
+    }
+
+    ///
+    /// Comment out the rows `first..=last` with the filetype's line comment marker, or wrap them
+    /// in its block comment when it has none; undo it when the rows are commented that way already.
+    /// Only the outermost block comment is removed. Returns whether the rows are now commented,
+    /// `None` when the filetype has no comments.
+    ///
+    pub fn toggle_comment(&mut self, first: usize, last: usize) -> Option<bool> {
+        let last = last.min(self.len().checked_sub(1)?);
+        if first > last {
+            return None;
+        }
+        let mut lines: Vec<String> = self.rows[first..=last]
+            .iter()
+            .map(|row| row.text().clone())
+            .collect();
+        let commented = if let Some(marker) = self.filetype.line_comment() {
+            toggle_line_comments(&mut lines, marker)
+        } else {
+            let (open, close) = self.filetype.block_comment()?;
+            toggle_block_comment(&mut lines, open, close)
+        };
+        let before = self.lines(first..last + 1);
+        for (y, line) in (first..=last).zip(lines) {
+            self.rows[y] = Row::from(&line[..]);
+        }
+        self.record_rows(first, before, last + 1 - first);
+        self.highlight_rows(first, last);
+        self.changed();
+        Some(commented)
+    }
+
+    ///
+    /// Number the rows `first..=last` with a sequence starting from the number at `column` of the first row:
+    /// in each row the number at (or after) the column is replaced, keeping zero padding.
+    /// Returns how many rows were updated, `None` when there's no number to start from.
+    ///
+    pub fn number_column(&mut self, first: usize, last: usize, column: usize) -> Option<usize> {
+        let (start, end) = number_at(self.rows.get(first)?.text(), column)?;
+        let chars: Vec<char> = self.rows[first].text().chars().collect();
+        let digits: String = chars[start..end].iter().collect();
+        let mut value: u128 = digits.parse().ok()?;
+        let mut updated = 0;
+        let last = last.min(self.len().saturating_sub(1));
+        let before = self.lines(first..last + 1);
+        for y in first..=last {
+            let (start, end) = match number_at(self.rows[y].text(), column) {
+                Some(range) => range,
+                None => continue,
+            };
+            let chars: Vec<char> = self.rows[y].text().chars().collect();
+            let width = if chars[start] == '0' { end - start } else { 0 };
+            let replacement = format!("{:0width$}", value, width = width);
+            let text: String = chars[..start]
+                .iter()
+                .chain(replacement.chars().collect::<Vec<char>>().iter())
+                .chain(chars[end..].iter())
+                .collect();
+            if text != *self.rows[y].text() {
+                self.rows[y] = Row::from(&text[..]);
+                updated += 1;
+            }
+            value = value.saturating_add(1);
+        }
+        if updated > 0 {
+            self.record_rows(first, before, last + 1 - first);
+            self.highlight_rows(first, last);
+            self.changed();
+        }
+        Some(updated)
+    }
+
+    ///
+    /// Replace the `len` characters at `at` with `replacement` as a single undo step,
+    /// returning where the replacement ends
+    ///
+    pub fn replace_at(&mut self, at: &Position, len: usize, replacement: &str) -> Position {
+        self.batch(|document| {
+            let end = Position {
+                x: at.x + len,
+                y: at.y,
+            };
+            document.delete_range(at, &end);
+            document.insert_text(replacement, at)
+        })
+    }
+
+    ///
+    /// Replace every occurrence of `query` (a single line) lying between `start` and `end`
+    /// with `replacement`, all at once. Returns how many occurrences were replaced.
+    ///
+    pub fn replace_all(
+        &mut self,
+        query: &str,
+        replacement: &str,
+        start: &Position,
+        end: &Position,
+    ) -> usize {
+        let needle: Vec<char> = query.chars().collect();
+        if needle.is_empty() || self.is_empty() {
+            return 0;
+        }
+        let replacement: Vec<char> = replacement.chars().collect();
+        let last = end.y.min(self.len() - 1);
+        let before = self.lines(start.y.min(last)..last + 1);
+        let mut replaced = 0;
+        let mut first_changed = None;
+        for y in start.y..=last {
+            let chars: Vec<char> = self.rows[y].text().chars().collect();
+            let from = if y == start.y {
+                start.x.min(chars.len())
+            } else {
+                0
+            };
+            let to = if y == end.y {
+                end.x.min(chars.len())
+            } else {
+                chars.len()
+            };
+            let mut text: Vec<char> = chars[..from].to_vec();
+            let mut x = from;
+            let mut found = false;
+            while x < to {
+                if x + needle.len() <= to && chars[x..x + needle.len()] == needle[..] {
+                    text.extend(&replacement);
+                    x += needle.len();
+                    replaced += 1;
+                    found = true;
                 } else {
-                    fs::OpenOptions::new()
-                        .create_new(true)
-                        .write(true)
-                        .open(&filepath)?
-                };
-                for row in &self.rows {
-                    file.write_all(row.text().as_bytes())?;
-                    file.write_all(b"\n")?;
+                    text.push(chars[x]);
+                    x += 1;
+                }
+            }
+            if found {
+                text.extend(&chars[to..]);
+                self.rows[y] = Row::from(&text.iter().collect::<String>()[..]);
+                first_changed = first_changed.or(Some(y));
+            }
+        }
+        if let Some(first) = first_changed {
+            let from = start.y.min(last);
+            self.record_rows(from, before, last + 1 - from);
+            self.highlight_rows(first, last);
+            self.changed();
+        }
+        replaced
+    }
+
+    ///
+    /// Pad the rows `first..=last` with spaces so the first `delimiter` of each lines up
+    /// with the rightmost one. Rows without the delimiter are left alone.
+    /// Returns how many rows changed.
+    ///
+    pub fn align(&mut self, first: usize, last: usize, delimiter: &str) -> usize {
+        let last = last.min(self.len().saturating_sub(1));
+        if self.is_empty() || first > last {
+            return 0;
+        }
+        let columns: Vec<Option<usize>> = self.rows[first..=last]
+            .iter()
+            .map(|row| {
+                let text = row.text();
+                text.find(delimiter)
+                    .map(|index| text[..index].chars().count())
+            })
+            .collect();
+        let target = match columns.iter().flatten().max() {
+            Some(&target) => target,
+            None => return 0,
+        };
+        let mut aligned = 0;
+        let before = self.lines(first..last + 1);
+        for (y, column) in (first..=last).zip(columns) {
+            if let Some(column) = column {
+                if column < target {
+                    let row = &mut self.rows[y];
+                    let tail = row.split(column);
+                    row.append(&Row::from(&" ".repeat(target - column)[..]));
+                    row.append(&tail);
+                    aligned += 1;
                 }
-                self.is_dirty = false;
+            }
+        }
+        if aligned > 0 {
+            self.record_rows(first, before, last + 1 - first);
+            self.highlight_rows(first, last);
+            self.changed();
+        }
+        aligned
+    }
+
+    /// The tab width and indent style used when the editorconfig doesn't give them
+    pub fn set_default_indentation(&mut self, tab_width: usize, soft_tabs: bool) {
+        self.default_tab_width = tab_width.max(1);
+        self.soft_tabs = soft_tabs;
+    }
+
+    ///
+    /// What a press of Tab inserts: a tab, or spaces when the editorconfig asks for them
+    /// (or, without a word from it, the user's config)
+    ///
+    pub fn indent_unit(&self) -> String {
+        let soft_tabs = match self.editorconfig.indent_style {
+            Some(style) => style == IndentStyle::Space,
+            None => self.soft_tabs,
+        };
+        if soft_tabs {
+            " ".repeat(
+                self.editorconfig
+                    .indent_size
+                    .unwrap_or(self.default_tab_width),
+            )
+        } else {
+            String::from("\t")
+        }
+    }
+
+    ///
+    /// How many columns a tab spans: the editorconfig's `tab_width`, else its `indent_size`,
+    /// else the user's default
+    ///
+    pub fn tab_width(&self) -> usize {
+        self.editorconfig
+            .tab_width
+            .or(self.editorconfig.indent_size)
+            .filter(|&width| width > 0)
+            .unwrap_or(self.default_tab_width)
+    }
+
+    ///
+    /// The first row after `from` wider than `limit` columns, wrapping around the end
+    /// of the document (`from` itself coming last)
+    ///
+    pub fn next_long_line(&self, from: usize, limit: usize) -> Option<usize> {
+        let tab_width = self.tab_width();
+        let len = self.len();
+        (1..=len)
+            .map(|offset| (from + offset) % len)
+            .find(|&y| self.rows[y].render_width(false, tab_width) > limit)
+    }
+
+    ///
+    /// Replace the character at the given position, inserting when past the end of the row
+    ///
+    pub fn replace_char(&mut self, c: char, pos: &Position) {
+        match self
+            .rows
+            .get(pos.y)
+            .and_then(|row| row.text().chars().nth(pos.x))
+        {
+            Some(replaced) if c != '\n' => {
+                self.history.record(Edit::Delete(pos.clone(), replaced));
+                self.history.record(Edit::Insert(pos.clone(), c));
+                let row = &mut self.rows[pos.y];
+                row.delete(pos.x);
+                row.insert(c, pos.x);
+                self.highlight_rows(pos.y, pos.y);
+                self.changed();
+            }
+            _ => self.insert(c, pos),
+        }
+    }
+
+    ///
+    /// Insert a whole string at the given position, new lines create new rows.
+    /// Returns the position right after the inserted text.
+    ///
+    pub fn insert_text(&mut self, text: &str, at: &Position) -> Position {
+        let mut pos = at.clone();
+        self.history.open_step();
+        for c in text.chars() {
+            self.insert(c, &pos);
+            if c == '\n' {
+                pos.y += 1;
+                pos.x = 0;
+            } else {
+                pos.x += 1;
+            }
+        }
+        if self.batch_depth == 0 {
+            self.history.close_step();
+        }
+        self.highlight_rows(at.y, pos.y);
+        pos
+    }
+
+    ///
+    /// The text between `start` (inclusive) and `end` (exclusive), rows joined by new lines
+    ///
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let mut text = String::new();
+        for y in start.y..=end.y {
+            let row = match self.rows.get(y) {
+                Some(row) => row,
+                None => break,
+            };
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            text.extend(row.text().chars().skip(from).take(to.saturating_sub(from)));
+            if y < end.y {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    ///
+    /// Where `text` (a single line) next appears from `at` on, wrapping around the end of the document
+    ///
+    pub fn find_occurrence(&self, text: &str, at: &Position) -> Option<Position> {
+        let needle: Vec<char> = text.chars().collect();
+        if needle.is_empty() || self.is_empty() {
+            return None;
+        }
+        let rows = (at.y..self.len()).chain(0..=at.y.min(self.len() - 1));
+        for (i, y) in rows.enumerate() {
+            let chars: Vec<char> = self.rows[y].text().chars().collect();
+            let from = if i == 0 { at.x } else { 0 };
+            if chars.len() < needle.len() || from > chars.len() - needle.len() {
+                continue;
+            }
+            if let Some(x) = (from..=chars.len() - needle.len())
+                .find(|&x| chars[x..x + needle.len()] == needle[..])
+            {
+                return Some(Position { x, y });
+            }
+        }
+        None
+    }
+
+    ///
+    /// Delete the text between `start` (inclusive) and `end` (exclusive), joining rows as needed
+    ///
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        if self.is_empty() || start.y >= self.len() {
+            return;
+        }
+        // the cursor may sit on the line past the end of the document
+        let end = if end.y >= self.len() {
+            let y = self.len() - 1;
+            Position {
+                x: self.rows[y].len(),
+                y,
+            }
+        } else {
+            end.clone()
+        };
+        let before = self.lines(start.y..end.y + 1);
+        let tail = self.rows[end.y].split(end.x);
+        let row = &mut self.rows[start.y];
+        row.split(start.x);
+        row.append(&tail);
+        if end.y > start.y {
+            self.rows.drain(start.y + 1..=end.y);
+        }
+        self.record_rows(start.y, before, 1);
+        self.highlight_rows(start.y, start.y);
+        self.changed();
+    }
+
+    ///
+    /// Shade the text between `start` (inclusive) and `end` (exclusive)
+    ///
+    pub fn shade(&mut self, start: &Position, end: &Position, shading_type: highlighting::Type) {
+        if self.is_empty() {
+            return;
+        }
+        let last = end.y.min(self.len().saturating_sub(1));
+        for y in start.y..=last {
+            let row = &mut self.rows[y];
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            row.shade(from, to, shading_type.clone());
+        }
+    }
+
+    ///
+    /// Run `edits` as a batch: the rows they change are highlighted once, when it returns,
+    /// rather than after every single edit, and a single undo step reverts them all.
+    ///
+    pub fn batch<T, F: FnOnce(&mut Document) -> T>(&mut self, edits: F) -> T {
+        self.begin_batch();
+        let result = edits(self);
+        self.end_batch();
+        result
+    }
+
+    /// Start deferring the highlighting of edited rows, until the matching `end_batch`
+    pub fn begin_batch(&mut self) {
+        self.batch_depth += 1;
+        self.history.open_step();
+    }
+
+    /// End a batch, the outermost one highlighting every row edited since it began
+    pub fn end_batch(&mut self) {
+        self.batch_depth = self.batch_depth.saturating_sub(1);
+        if self.batch_depth > 0 {
+            return;
+        }
+        self.history.close_step();
+        if let Some(first) = self.batch_first.take() {
+            // edited rows may have been pushed down by the following edits
+            let last = (first..self.len())
+                .rev()
+                .find(|&y| self.rows[y].start_state().is_none())
+                .unwrap_or(first);
+            self.highlight_rows(first, last);
+        }
+    }
+
+    ///
+    /// Recompute the highlighting of the rows between `first` and `last` (inclusive).
+    /// The following rows are highlighted again only until one would start in the same state
+    /// as last time (e.g. until past the end of a multiline comment that was opened or closed),
+    /// so an edit costs a bounded number of rows rather than the rest of the file.
+    ///
+    pub fn highlight_rows(&mut self, first: usize, last: usize) {
+        if self.batch_depth > 0 {
+            for row in self.rows.iter_mut().take(last + 1).skip(first) {
+                row.mark_stale();
+            }
+            self.batch_first = Some(self.batch_first.map_or(first, |y| y.min(first)));
+            return;
+        }
+        let mut state = match first.checked_sub(1).and_then(|y| self.rows.get(y)) {
+            Some(previous) => previous.end_state(),
+            None => highlighting::State::default(),
+        };
+        for y in first..self.rows.len() {
+            let row = &mut self.rows[y];
+            if y > last && row.start_state() == Some(state) {
+                break;
+            }
+            state = row.highlight(&*self.highlighter, state, &self.search_string);
+        }
+        self.shade_conflicts();
+    }
+
+    ///
+    /// Highlight the `count` rows from `first` on again, whatever state they were left in.
+    /// Returns the row to continue from, `None` once the end of the document is reached.
+    ///
+    pub fn rehighlight_from(&mut self, first: usize, count: usize) -> Option<usize> {
+        let last = first.saturating_add(count).min(self.len());
+        let mut state = match first.checked_sub(1).and_then(|y| self.rows.get(y)) {
+            Some(previous) => previous.end_state(),
+            None => highlighting::State::default(),
+        };
+        for y in first..last {
+            state = self.rows[y].highlight(&*self.highlighter, state, &self.search_string);
+        }
+        self.shade_conflicts();
+        if last < self.len() {
+            Some(last)
+        } else {
+            None
+        }
+    }
+
+    /// The merge conflicts left in the file, if it had some when opened
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    ///
+    /// Resolve the conflict around `row`, keeping our side, theirs or both and dropping the markers.
+    /// Returns the first row of what was kept, `None` when `row` is not in a conflict.
+    ///
+    pub fn resolve_conflict(&mut self, row: usize, resolution: Resolution) -> Option<usize> {
+        let conflict = self
+            .conflicts
+            .iter()
+            .find(|conflict| conflict.contains(row))?
+            .clone();
+        let kept: Vec<Row> = conflict
+            .kept(resolution)
+            .into_iter()
+            .map(|y| Row::from(&self.rows[y].text()[..]))
+            .collect();
+        let before = self.lines(conflict.start..conflict.end + 1);
+        let count = kept.len();
+        self.rows.splice(conflict.start..=conflict.end, kept);
+        self.record_rows(conflict.start, before, count);
+        self.changed();
+        self.hightlight();
+        Some(conflict.start)
+    }
+
+    ///
+    /// Find the conflicts again and shade their sides and markers. Rows are shaded afresh
+    /// whenever highlighted, so this follows every highlighting pass.
+    ///
+    fn shade_conflicts(&mut self) {
+        if self.conflicts.is_empty() {
+            return;
+        }
+        self.conflicts = conflict::find_conflicts(self.rows.iter().map(|row| row.text().as_str()));
+        for conflict in &self.conflicts {
+            for y in conflict.start..=conflict.end {
+                let shading = if conflict.ours().contains(&y) {
+                    highlighting::Type::ConflictOurs
+                } else if conflict.theirs().contains(&y) {
+                    highlighting::Type::ConflictTheirs
+                } else {
+                    highlighting::Type::ConflictMarker
+                };
+                let row = &mut self.rows[y];
+                let len = row.len();
+                row.shade(0, len, shading);
+            }
+        }
+    }
+
+    /// Highlight with `highlighter` instead of the built in one
+    pub fn set_highlighter(&mut self, highlighter: Rc<dyn Highlighter>) {
+        self.highlighter = highlighter;
+        self.hightlight();
+    }
+
+    pub fn with_highlighter(mut self, highlighter: Rc<dyn Highlighter>) -> Self {
+        self.set_highlighter(highlighter);
+        self
+    }
+
+    ///
+    /// Write the document to its file through a buffered writer.
+    /// Lines end the way they did in the file (unless the editorconfig says otherwise),
+    /// and the last one is ended only if it was.
+    /// With `sync_to_disk` the data is also flushed to the device before returning.
+    /// Returns the number of bytes written (zero when there was nothing to save).
+    ///
+    pub fn save(&mut self, sync_to_disk: bool) -> Result<usize, EditorError> {
+        if !self.is_dirty() || self.read_only {
+            return Ok(0);
+        }
+        let filename = match &self.filename {
+            Some(filename) => filename.clone(),
+            None => return Ok(0),
+        };
+        let written = self
+            .write_to(&filename, sync_to_disk)
+            .map_err(|error| EditorError::io("write", &filename, error))?;
+        self.disk_modified = modified_time(&filename);
+        self.is_dirty = false;
+        Ok(written)
+    }
+
+    /// Write the rows to `filename`, remembering the hash of what was written
+    fn write_to(&mut self, filename: &str, sync_to_disk: bool) -> Result<usize, std::io::Error> {
+        let mut written = 0;
+        let filepath = std::path::Path::new(filename);
+        let file = if filepath.exists() {
+            fs::File::create(&filepath)?
+        } else {
+            fs::OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&filepath)?
+        };
+        let mut writer = BufWriter::new(file);
+        let mut hasher = DefaultHasher::new();
+        self.saved_bytes(|bytes| {
+            writer.write_all(bytes)?;
+            hasher.write(bytes);
+            written += bytes.len();
+            Ok(())
+        })?;
+        writer.flush()?;
+        if sync_to_disk {
+            writer.get_ref().sync_all()?;
+        }
+        self.disk_hash = Some(hasher.finish());
+        Ok(written)
+    }
+
+    ///
+    /// Feed `write` the bytes saving would write, in order: rows with the editorconfig's
+    /// line endings, trailing whitespace and final newline settings applied.
+    ///
+    fn saved_bytes<F>(&self, mut write: F) -> Result<(), std::io::Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), std::io::Error>,
+    {
+        let line_ending = self.line_ending();
+        let trim = self.editorconfig.trim_trailing_whitespace == Some(true);
+        let final_newline = self
+            .editorconfig
+            .insert_final_newline
+            .unwrap_or(self.final_newline);
+        for (index, row) in self.rows.iter().enumerate() {
+            let text = if trim {
+                row.text().trim_end()
+            } else {
+                row.text().as_str()
+            };
+            write(text.as_bytes())?;
+            if index + 1 < self.rows.len() || final_newline {
+                write(line_ending)?;
             }
         }
         Ok(())
     }
 
+    /// The bytes the file would hold once saved
+    pub fn contents(&self) -> Vec<u8> {
+        let mut contents = Vec::new();
+        let _ = self.saved_bytes(|bytes| {
+            contents.extend_from_slice(bytes);
+            Ok(())
+        });
+        contents
+    }
+
+    /// The text between two positions with the lines ended as in the saved file
+    pub fn text_range_bytes(&self, start: &Position, end: &Position) -> Vec<u8> {
+        let line_ending = String::from_utf8_lossy(self.line_ending()).to_string();
+        self.text_range(start, end)
+            .replace('\n', &line_ending)
+            .into_bytes()
+    }
+
+    ///
+    /// Whether saving would overwrite content we have not seen:
+    /// the file changed on disk since it was opened/saved, or it exists but was never read.
+    ///
+    pub fn changed_on_disk(&self) -> bool {
+        let filename = match &self.filename {
+            Some(filename) => filename,
+            None => return false,
+        };
+        if !std::path::Path::new(filename).exists() {
+            return false;
+        }
+        let known_hash = match self.disk_hash {
+            Some(hash) => hash,
+            None => return true,
+        };
+        // same modification time, no need to read the whole file back
+        if self.disk_modified.is_some() && self.disk_modified == modified_time(filename) {
+            return false;
+        }
+        match fs::read(filename) {
+            Ok(contents) => content_hash(&contents) != known_hash,
+            Err(_) => true,
+        }
+    }
+
+    ///
+    /// Compare the buffer with the file on disk, a file not saved yet is all added lines
+    ///
+    pub fn diff_saved(&self) -> Result<diff::Diff, EditorError> {
+        let saved = match &self.filename {
+            Some(filename) if std::path::Path::new(filename).exists() => {
+                fs::read_to_string(filename)
+                    .map_err(|error| EditorError::io("read", filename, error))?
+            }
+            _ => String::new(),
+        };
+        let old: Vec<&str> = saved.lines().collect();
+        let new: Vec<&str> = self.rows.iter().map(|row| row.text().as_str()).collect();
+        Ok(diff::diff_lines(&old, &new))
+    }
+
+    /// Whether the file's modification time moved since we last read or wrote it
+    pub fn modified_on_disk(&self) -> bool {
+        match &self.filename {
+            Some(filename) => {
+                let modified = modified_time(filename);
+                modified.is_some() && modified != self.disk_modified
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Read the file again from disk, dropping the in memory content.
+    /// The active search stays highlighted.
+    ///
+    pub fn reload(&mut self) -> Result<(), EditorError> {
+        let filename = match &self.filename {
+            Some(filename) => filename.clone(),
+            None => return Ok(()),
+        };
+        let mut document = Document::options()
+            .read_only(self.read_only)
+            .highlighter(Rc::clone(&self.highlighter))
+            .open(&filename)?;
+        document.search_string = self.search_string.take();
+        document.last_search = self.last_search.take();
+        document.revision = self.revision + 1;
+        document.hightlight();
+        *self = document;
+        Ok(())
+    }
+
+    ///
+    /// The start and end of the next match of `query` from `at` on, or of the previous one
+    /// ending before `at`. Matches are shaded from then on.
+    ///
     pub fn find(
         &mut self,
-        query: &String,
+        query: &Pattern,
         at: Position,
         direction: SearchDirection,
-    ) -> Option<Position> {
+    ) -> Option<(Position, Position)> {
+        if !query.is_empty() {
+            self.last_search = Some(query.clone());
+        }
         let mut pos = at;
         let start;
         let end;
@@ -143,35 +1498,23 @@ impl Document {
                 if y > pos.y {
                     pos.x = 0;
                 }
-                if let Some(x) = row.find(query, pos.x, direction) {
-                    self.search_string = Some(query.clone());
-                    row.highlight(&self.filetype, &Option::Some(query.clone()));
-                    return Some(Position { x, y });
+                if let Some(range) = row.find(query, pos.x, direction) {
+                    self.highlight_matches(query);
+                    return Some((Position { x: range.start, y }, Position { x: range.end, y }));
                 }
-                // if let Some(row) = self.row_mut(y) {
-                //     // moving to new line, restart x position
-                //     if y > pos.y {
-                //         pos.x = 0;
-                //     }
-                //     if let Some(x) = row.find(query, pos.x, direction) {
-                //         // row.highlight(&self.filetype, &Option::Some(query.clone()));
-                //         self.search_string = Some(query.clone());
-                //         return Some(Position { x, y });
-                //     }
-                // }
             }
         } else {
             end = 0;
-            start = pos.y;
+            start = pos.y.min(self.rows.len().saturating_sub(1));
             for y in (end..=start).rev() {
                 let row = &mut self.rows[y];
+                // on the lines above, matches up to the end of the line count
                 if y < pos.y {
-                    pos.x = row.len().saturating_sub(1);
+                    pos.x = row.len() + 1;
                 }
-                if let Some(x) = row.find(query, pos.x, direction) {
-                    self.search_string = Some(query.clone());
-                    row.highlight(&self.filetype, &Option::Some(query.clone()));
-                    return Some(Position { x, y });
+                if let Some(range) = row.find(query, pos.x, direction) {
+                    self.highlight_matches(query);
+                    return Some((Position { x: range.start, y }, Position { x: range.end, y }));
                 }
             }
         }
@@ -179,9 +1522,473 @@ impl Document {
         None
     }
 
+    /// The rows holding at least one match of `query`
+    pub fn match_rows(&self, query: &Pattern) -> Vec<usize> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+        (0..self.len())
+            .filter(|&y| query.is_match(self.rows[y].text()))
+            .collect()
+    }
+
+    /// The query of the last search in this document
+    pub fn last_search(&self) -> Option<&Pattern> {
+        self.last_search.as_ref()
+    }
+
+    /// Shade the matches of `query`, rehighlighting every row when the query changed
+    fn highlight_matches(&mut self, query: &Pattern) {
+        if self.search_string.as_ref() != Some(query) {
+            self.search_string = Some(query.clone());
+            self.hightlight();
+        }
+    }
+
     pub fn hightlight(&mut self) {
-        for i in 0..self.rows.len() {
-            &mut self.rows[i].highlight(&self.filetype, &self.search_string);
+        let mut state = highlighting::State::default();
+        for row in self.rows.iter_mut() {
+            state = row.highlight(&*self.highlighter, state, &self.search_string);
+        }
+        self.shade_conflicts();
+    }
+}
+
+///
+/// Split text into lines ended by `\n`, `\r\n`, a lone `\r` or the Unicode line and paragraph
+/// separators. Returns the lines, the most common of the first three endings (the Unicode
+/// separators are saved as that one) and whether the last line was ended.
+///
+fn split_lines(contents: &str) -> (Vec<&str>, Option<EndOfLine>, bool) {
+    let mut lines = Vec::new();
+    let mut counts = [0; 3];
+    let mut start = 0;
+    let mut chars = contents.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            '\n' => {
+                counts[0] += 1;
+                i + 1
+            }
+            '\r' if chars.peek().map(|(_, next)| *next) == Some('\n') => {
+                chars.next();
+                counts[1] += 1;
+                i + 2
+            }
+            '\r' => {
+                counts[2] += 1;
+                i + 1
+            }
+            '\u{2028}' | '\u{2029}' => i + c.len_utf8(),
+            _ => continue,
+        };
+        lines.push(&contents[start..i]);
+        start = end;
+    }
+    let final_newline = start == contents.len();
+    if !final_newline {
+        lines.push(&contents[start..]);
+    }
+    let endings = [EndOfLine::Lf, EndOfLine::CrLf, EndOfLine::Cr];
+    let most_common = (0..3)
+        .filter(|&index| counts[index] > 0)
+        .max_by_key(|&index| (counts[index], std::cmp::Reverse(index)))
+        .map(|index| endings[index]);
+    (lines, most_common, final_newline)
+}
+
+/// Comment out the non blank lines at their common indentation, or uncomment them when all are
+fn toggle_line_comments(lines: &mut Vec<String>, marker: &str) -> bool {
+    let commented = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .all(|line| line.trim_start().starts_with(marker));
+    if commented {
+        for line in lines.iter_mut() {
+            if let Some(index) = line.find(marker) {
+                let mut end = index + marker.len();
+                if line[end..].starts_with(' ') {
+                    end += 1;
+                }
+                line.replace_range(index..end, "");
+            }
         }
+        return false;
+    }
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    for line in lines.iter_mut() {
+        if !line.trim().is_empty() {
+            line.insert_str(indent, &format!("{} ", marker));
+        }
+    }
+    true
+}
+
+/// Wrap the lines in a block comment, or remove the outermost one when they are wrapped already
+fn toggle_block_comment(lines: &mut Vec<String>, open: &str, close: &str) -> bool {
+    let first = lines.iter().position(|line| !line.trim().is_empty());
+    let last = lines.iter().rposition(|line| !line.trim().is_empty());
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return false,
+    };
+    let wrapped = lines[first].trim_start().starts_with(open)
+        && lines[last].trim_end().ends_with(close)
+        && (first != last || lines[first].trim().len() >= open.len() + close.len())
+        && first_comment_ends_last(&lines[first..=last], open, close);
+    if wrapped {
+        let line = &mut lines[last];
+        let mut start = line.rfind(close).unwrap_or(0);
+        if line[..start].ends_with(' ') {
+            start -= 1;
+        }
+        let end = line.rfind(close).map_or(start, |index| index + close.len());
+        line.replace_range(start..end, "");
+        let line = &mut lines[first];
+        let start = line.find(open).unwrap_or(0);
+        let mut end = start + open.len();
+        if line[end..].starts_with(' ') {
+            end += 1;
+        }
+        line.replace_range(start..end, "");
+        return false;
+    }
+    let indent = lines[first].len() - lines[first].trim_start().len();
+    lines[first].insert_str(indent, &format!("{} ", open));
+    let end = lines[last].trim_end().len();
+    lines[last].insert_str(end, &format!(" {}", close));
+    true
+}
+
+/// Whether the comment opened at the start of `lines` is closed by the closer ending them,
+/// and not by one before it as in `/* a */ x /* b */`
+fn first_comment_ends_last(lines: &[String], open: &str, close: &str) -> bool {
+    let mut from = lines[0].find(open).map_or(0, |index| index + open.len());
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(found) = line[from..].find(close) {
+            return index == lines.len() - 1 && from + found + close.len() == line.trim_end().len();
+        }
+        from = 0;
+    }
+    false
+}
+
+/// Character range of the digits under `column`, or of the first ones after it
+fn number_at(text: &str, column: usize) -> Option<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut start = column.min(chars.len());
+    if chars.get(start).map_or(false, |c| c.is_ascii_digit()) {
+        while start > 0 && chars[start - 1].is_ascii_digit() {
+            start -= 1;
+        }
+    } else {
+        start += chars[start..].iter().position(|c| c.is_ascii_digit())?;
+    }
+    let len = chars[start..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    Some((start, start + len))
+}
+
+fn content_hash(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(contents);
+    hasher.finish()
+}
+
+fn modified_time(filename: &str) -> Option<SystemTime> {
+    fs::metadata(filename)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Text files don't contain NUL bytes, checking the beginning is enough to tell
+fn looks_binary(contents: &[u8]) -> bool {
+    contents.iter().take(8000).any(|byte| *byte == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn document(lines: &[&str]) -> Document {
+        Document {
+            rows: lines.iter().map(|line| Row::from(*line)).collect(),
+            ..Document::default()
+        }
+    }
+
+    /// The built in Rust highlighter, counting the rows it is asked to highlight
+    struct CountingHighlighter {
+        highlighter: TokenHighlighter,
+        rows: Cell<usize>,
+    }
+
+    impl Highlighter for CountingHighlighter {
+        fn highlight(
+            &self,
+            line: &str,
+            start_state: highlighting::State,
+        ) -> (Vec<(Range<usize>, highlighting::Type)>, highlighting::State) {
+            self.rows.set(self.rows.get() + 1);
+            self.highlighter.highlight(line, start_state)
+        }
+    }
+
+    /// A highlighted document of `lines` and its highlighter, the count starting from zero
+    fn counted(lines: &[&str]) -> (Document, Rc<CountingHighlighter>) {
+        let highlighter = Rc::new(CountingHighlighter {
+            highlighter: TokenHighlighter::new(FileType::from("main.rs")),
+            rows: Cell::new(0),
+        });
+        let document = document(lines).with_highlighter(highlighter.clone());
+        highlighter.rows.set(0);
+        (document, highlighter)
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        document
+            .rows
+            .iter()
+            .map(|row| row.text().as_str())
+            .collect()
+    }
+
+    #[test]
+    fn delete_to_eol_mid_line() {
+        let mut document = document(&["hello world", "next"]);
+        document.delete_to_eol(&Position { x: 5, y: 0 });
+        assert_eq!(lines(&document), ["hello", "next"]);
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn delete_to_eol_at_end_of_line_keeps_the_next_line() {
+        let mut document = document(&["hello", "next"]);
+        document.delete_to_eol(&Position { x: 5, y: 0 });
+        assert_eq!(lines(&document), ["hello", "next"]);
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn delete_to_eol_on_last_row() {
+        let mut document = document(&["first", "last row"]);
+        document.delete_to_eol(&Position { x: 4, y: 1 });
+        assert_eq!(lines(&document), ["first", "last"]);
+        document.delete_to_eol(&Position { x: 0, y: 1 });
+        assert_eq!(lines(&document), ["first", ""]);
+    }
+
+    #[test]
+    fn delete_line_mid_document() {
+        let mut document = document(&["one", "two", "three"]);
+        document.delete_line(1);
+        assert_eq!(lines(&document), ["one", "three"]);
+    }
+
+    #[test]
+    fn delete_line_on_last_row() {
+        let mut document = document(&["one", "two", "three"]);
+        document.delete_line(2);
+        assert_eq!(lines(&document), ["one", "two"]);
+        document.delete_line(2);
+        assert_eq!(lines(&document), ["one", "two"]);
+    }
+
+    #[test]
+    fn delete_only_line_leaves_an_empty_document() {
+        let mut document = document(&["alone"]);
+        document.delete_line(0);
+        assert!(document.is_empty());
+        assert!(document.is_dirty());
+        document.undo();
+        assert_eq!(lines(&document), ["alone"]);
+    }
+
+    #[test]
+    fn opening_a_comment_highlights_a_bounded_number_of_rows() {
+        let mut lines = vec!["fn main() {", "    let a = 1;", "    let b = 2; */"];
+        lines.resize(20_000, "    let c = a + b;");
+        let (mut document, highlighter) = counted(&lines);
+        document.insert('/', &Position { x: 0, y: 1 });
+        document.insert('*', &Position { x: 1, y: 1 });
+        assert_eq!(
+            document.rows[2].highlighting()[0],
+            highlighting::Type::MultilineComment
+        );
+        assert_ne!(
+            document.rows[3].highlighting()[4],
+            highlighting::Type::MultilineComment
+        );
+        // the row typed in twice, and once the row the comment now ends in:
+        // the rows after it start in the same state as before
+        assert_eq!(highlighter.rows.get(), 3);
+    }
+
+    #[test]
+    fn undo_and_redo_highlight_only_the_edited_row() {
+        let lines = vec!["let a = 1;"; 5_000];
+        let (mut document, highlighter) = counted(&lines);
+        document.insert('x', &Position { x: 0, y: 2_500 });
+        highlighter.rows.set(0);
+        document.undo();
+        assert_eq!(highlighter.rows.get(), 1);
+        document.redo();
+        assert_eq!(highlighter.rows.get(), 2);
+        assert_eq!(document.rows[2_500].text(), "xlet a = 1;");
+    }
+
+    #[test]
+    fn three_line_block_comment_colors_all_three_lines() {
+        let (document, _) = counted(&["/* one", "two", "three */", "let x = 1;"]);
+        for y in 0..3 {
+            let row = &document.rows[y];
+            assert!(
+                row.highlighting()
+                    .iter()
+                    .all(|t| *t == highlighting::Type::MultilineComment),
+                "row {}",
+                y
+            );
+        }
+        assert_eq!(
+            document.rows[3].highlighting()[0],
+            highlighting::Type::PrimaryKeywords
+        );
+    }
+
+    #[test]
+    fn saving_keeps_crlf_line_endings() {
+        let path =
+            std::env::temp_dir().join(format!("text_editor_crlf_{}.txt", std::process::id()));
+        let filename = path.to_str().unwrap();
+        fs::write(&path, b"one\r\ntwo\r\n").unwrap();
+        let mut document = Document::open(filename).unwrap();
+        document.insert('!', &Position { x: 3, y: 0 });
+        document.insert('\n', &Position { x: 1, y: 1 });
+        let expected = b"one!\r\nt\r\nwo\r\n";
+        assert_eq!(document.contents(), expected);
+        document.save(false).unwrap();
+        let saved = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(saved, expected);
+    }
+
+    #[test]
+    fn open_a_one_megabyte_single_line_file() {
+        let path = std::env::temp_dir().join(format!("text_editor_long_{}.js", std::process::id()));
+        let line = "var a=1;".repeat(128 * 1024);
+        fs::write(&path, &line).unwrap();
+        let document = Document::open(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        let document = document.unwrap();
+        assert_eq!(document.len(), 1);
+        let row = &document.rows[0];
+        assert_eq!(row.len(), 1024 * 1024);
+        // too long to be tokenized
+        assert!(row
+            .highlighting()
+            .iter()
+            .all(|t| *t == highlighting::Type::None));
+        assert!(!row.render(0, 80, false, 4).is_empty());
+    }
+
+    #[test]
+    fn refuse_binary_files() {
+        let path = std::env::temp_dir().join(format!("text_editor_binary_{}", std::process::id()));
+        fs::write(&path, b"ELF\0\0\x01").unwrap();
+        let document = Document::open(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        match document {
+            Err(EditorError::Binary { .. }) => (),
+            _ => panic!("a binary file was opened"),
+        }
+    }
+
+    #[test]
+    fn insert_on_the_virtual_last_line() {
+        let mut document = Document::default();
+        document.insert('a', &Position { x: 0, y: 0 });
+        assert_eq!(lines(&document), ["a"]);
+        // past the virtual line nothing happens
+        document.insert('b', &Position { x: 0, y: 2 });
+        assert_eq!(lines(&document), ["a"]);
+        document.insert('c', &Position { x: 0, y: 1 });
+        assert_eq!(lines(&document), ["a", "c"]);
+        document.insert('\n', &Position { x: 0, y: 2 });
+        assert_eq!(lines(&document), ["a", "c", "", ""]);
+        // the row appended to insert into goes away with what was inserted
+        document.undo();
+        assert_eq!(lines(&document), ["a", "c"]);
+        document.undo();
+        assert_eq!(lines(&document), ["a"]);
+    }
+
+    #[test]
+    fn each_buffer_remembers_its_own_search() {
+        let shaded = |document: &Document, y: usize| {
+            document.rows[y]
+                .render(0, 80, false, 4)
+                .contains(&crossterm::SetBg(highlighting::Type::Match.to_color()).to_string())
+        };
+        let mut first = document(&["apple pie", "pear"]);
+        let mut second = document(&["pear tart", "apple"]);
+        first.hightlight();
+        second.hightlight();
+        let apple = Pattern::new("apple", false).unwrap();
+        assert!(first
+            .find(&apple, Position::default(), SearchDirection::Forward)
+            .is_some());
+        // switching to the other buffer: nothing searched there yet
+        assert_eq!(first.last_search(), Some(&apple));
+        assert_eq!(second.last_search(), None);
+        assert!(shaded(&first, 0));
+        assert!(!shaded(&second, 1));
+
+        let pear = Pattern::new("pear", false).unwrap();
+        assert_eq!(
+            second.find(&pear, Position::default(), SearchDirection::Forward),
+            Some((Position { x: 0, y: 0 }, Position { x: 4, y: 0 }))
+        );
+        // and back, the first buffer still searches for its own query
+        assert_eq!(first.last_search(), Some(&apple));
+        assert!(!shaded(&first, 1));
+        assert_eq!(
+            first.find(&apple, Position { x: 1, y: 0 }, SearchDirection::Forward),
+            None
+        );
+    }
+
+    #[test]
+    fn block_comment_toggle_keeps_adjacent_comments() {
+        let toggle = |text: &[&str]| {
+            let mut lines: Vec<String> = text.iter().map(|line| line.to_string()).collect();
+            let wrapped = toggle_block_comment(&mut lines, "/*", "*/");
+            (wrapped, lines)
+        };
+        assert_eq!(toggle(&["  /* a */"]), (false, vec!["  a".to_string()]));
+        assert_eq!(
+            toggle(&["/* a", "b */"]),
+            (false, vec!["a".to_string(), "b".to_string()])
+        );
+        // two comments, not one around the line
+        assert_eq!(
+            toggle(&["/* a */ x /* b */"]),
+            (true, vec!["/* /* a */ x /* b */ */".to_string()])
+        );
+        assert_eq!(
+            toggle(&["/* a */", "/* b */"]),
+            (
+                true,
+                vec!["/* /* a */".to_string(), "/* b */ */".to_string()]
+            )
+        );
     }
 }
@@ -1,13 +1,52 @@
+use crate::clipboard;
+use crate::config::Config;
+use crate::conflict::Resolution;
+use crate::diff::LineChange;
 use crate::document::Document;
 use crate::document::SearchDirection;
+use crate::editorconfig::{EndOfLine, IndentStyle};
+use crate::error::EditorError;
+use crate::export;
+use crate::filetype::FileType;
+use crate::highlighting;
+use crate::highlighting::Highlighter;
+use crate::pattern::Pattern;
 use crate::row::Row;
 use crate::terminal::Color;
 use crate::terminal::Terminal;
-use crate::terminal::{InputEvent, KeyEvent};
+use crate::terminal::{InputEvent, KeyEvent, MouseButton, MouseEvent};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::result::Result;
 use std::time::Duration;
 use std::time::Instant;
 
+/// Largest repeat count accepted before a command
+const MAX_REPEAT_COUNT: usize = 10_000;
+
+/// Most keys handled between two redraws when they arrive faster than we draw
+const MAX_KEYS_PER_REDRAW: usize = 1_000;
+
+/// Rows scrolled by a notch of the mouse wheel
+const WHEEL_LINES: usize = 3;
+
+/// Rows highlighted again per idle tick by the idle rehighlight
+const IDLE_REHIGHLIGHT_ROWS: usize = 5_000;
+
+/// How many closed buffers can be reopened
+const MAX_CLOSED_BUFFERS: usize = 20;
+
+/// Files and directories marking the root of a project
+const PROJECT_MARKERS: [&str; 6] = [
+    ".git",
+    ".hg",
+    "Cargo.toml",
+    "package.json",
+    "go.mod",
+    "pyproject.toml",
+];
+
 /// Console Editor
 ///
 pub struct Editor {
@@ -17,63 +56,493 @@ pub struct Editor {
     document: Document,
     offset: Position,
     status_message: StatusMessage,
+    jump_list: JumpList,
+    config: Config,
+    // the command line settings, applied to every file opened
+    options: CliOptions,
+    selection: Option<Selection>,
+    // Ctrl-B was pressed, moving the cursor grows the selection like Shift + arrows
+    selecting: bool,
+    // the bracket under the cursor and its match, as last shaded
+    shaded_brackets: Vec<Position>,
+    // further selections (or bare cursors) edited along with the main one, Esc drops them
+    other_cursors: Vec<Selection>,
+    // other open files, the active one is edited through `document`
+    buffers: Vec<Buffer>,
+    active_buffer: usize,
+    // files of the buffers closed so far and where the cursor was, the latest last
+    closed_buffers: Vec<(String, Position)>,
+    // keep reloading the file and showing its end, like `tail -f`
+    follow: bool,
+    // typing replaces the character under the cursor
+    overwrite: bool,
+    // caret notation for control characters
+    show_control: bool,
+    // line numbers in a gutter left of the text
+    show_line_numbers: bool,
+    // Ctrl-Q presses still needed to quit with unsaved changes
+    quit_times: usize,
+    // searches compile the query as a regular expression
+    regex_search: bool,
+    yank_ring: YankRing,
+    // extent of the paste just made, cleared by any other command
+    last_paste: Option<Paste>,
+    // nearest ancestor of the first file (or working directory) holding a project marker
+    project_root: Option<PathBuf>,
+    // highlighters registered by filetype name, replacing the built in one
+    highlighters: HashMap<String, Rc<dyn Highlighter>>,
+    // byte offset and code point of the character under the cursor in the status bar
+    show_char_info: bool,
+    // revision of the document when last looked at, and when it last changed
+    seen_revision: u64,
+    last_edit: Instant,
+    // where the idle rehighlight is at, `None` when there's nothing to do
+    rehighlight_row: Option<usize>,
+    // characters replaced by the paste substitutions since the last redraw
+    sanitized_chars: usize,
+    // column vertical moves try to return to, set by the last horizontal move
+    goal_column: Option<usize>,
+    // repeat count typed before a command
+    pending_count: Option<usize>,
+    // Alt-q was pressed, the next character is the pair to surround the word with
+    surround_pending: bool,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+#[derive(PartialEq)]
+enum Severity {
+    Info,
+    Error,
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
+    severity: Severity,
+    // how long the message stays, the configured default when not set
+    timeout: Option<Duration>,
 }
 impl Default for StatusMessage {
     fn default() -> Self {
-        Self {
-            text: String::new(),
-            time: Instant::now(),
-        }
+        Self::info(String::new())
     }
 }
 impl StatusMessage {
-    fn from(status: String) -> Self {
+    /// A message expiring after the configured timeout
+    fn info(status: String) -> Self {
         Self {
             text: status,
             time: Instant::now(),
+            severity: Severity::Info,
+            timeout: None,
+        }
+    }
+
+    /// A message shown until the next keystroke
+    fn error(status: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            ..Self::info(status)
+        }
+    }
+
+    fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+///
+/// Settings given on the command line, they win over the `.editorconfig` of the files
+/// (which wins over the config file)
+///
+#[derive(Default)]
+struct CliOptions {
+    /// `--readonly` or `-R`: the files given are opened for viewing only
+    read_only: bool,
+    /// `--tab-width=N`
+    tab_width: Option<usize>,
+    /// `--soft-tabs` (indent with spaces) or `--hard-tabs`
+    soft_tabs: Option<bool>,
+    /// `--eol=lf`, `--eol=crlf` or `--eol=cr`
+    end_of_line: Option<EndOfLine>,
+}
+
+impl CliOptions {
+    /// Take the options out of `args`, leaving the file names. Returns them with the problems found.
+    fn parse(args: &mut Vec<String>) -> (Self, Vec<String>) {
+        let mut options = Self::default();
+        let mut warnings = Vec::new();
+        args.retain(|arg| {
+            let (name, value) = match arg.find('=') {
+                Some(index) => (&arg[..index], Some(&arg[index + 1..])),
+                None => (arg.as_str(), None),
+            };
+            match (name, value) {
+                ("--readonly", None) | ("-R", None) => options.read_only = true,
+                ("--soft-tabs", None) => options.soft_tabs = Some(true),
+                ("--hard-tabs", None) => options.soft_tabs = Some(false),
+                ("--tab-width", Some(value)) => match value.parse() {
+                    Ok(width) if width > 0 => options.tab_width = Some(width),
+                    _ => warnings.push(format!("Invalid tab width: {}", value)),
+                },
+                ("--eol", Some(value)) => match EndOfLine::from_name(value) {
+                    Some(end_of_line) => options.end_of_line = Some(end_of_line),
+                    None => warnings.push(format!("Unknown line ending: {}", value)),
+                },
+                _ if arg.starts_with("--") => warnings.push(format!("Unknown option: {}", arg)),
+                _ => return true,
+            }
+            false
+        });
+        (options, warnings)
+    }
+
+    /// Set the indentation and line ending of `document` over what its `.editorconfig` says
+    fn apply(&self, document: &mut Document) {
+        if let Some(width) = self.tab_width {
+            document.editorconfig.tab_width = Some(width);
+            document.editorconfig.indent_size = Some(width);
+        }
+        if let Some(soft_tabs) = self.soft_tabs {
+            document.editorconfig.indent_style = Some(if soft_tabs {
+                IndentStyle::Space
+            } else {
+                IndentStyle::Tab
+            });
+        }
+        if let Some(end_of_line) = self.end_of_line {
+            document.editorconfig.end_of_line = Some(end_of_line);
+        }
+    }
+}
+
+/// A document in the background along with where we were looking at it
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+}
+
+/// Text selected between the position where the selection started and the cursor
+#[derive(Debug, Clone)]
+struct Selection {
+    anchor: Position,
+    head: Position,
+}
+impl Selection {
+    /// The selection bounds ordered as they appear in the document
+    fn range(&self) -> (Position, Position) {
+        let Selection { anchor, head } = self;
+        if (anchor.y, anchor.x) <= (head.y, head.x) {
+            (anchor.clone(), head.clone())
+        } else {
+            (head.clone(), anchor.clone())
+        }
+    }
+}
+
+/// Positions the cursor jumped away from (search, goto...), navigable back and forth
+#[derive(Default)]
+struct JumpList {
+    positions: Vec<Position>,
+    index: usize,
+}
+impl JumpList {
+    const MAX_JUMPS: usize = 100;
+
+    /// Record a position we are jumping away from, dropping any "forward" history
+    fn push(&mut self, position: Position) {
+        self.positions.truncate(self.index);
+        if self.positions.last() != Some(&position) {
+            self.positions.push(position);
+        }
+        if self.positions.len() > Self::MAX_JUMPS {
+            self.positions.remove(0);
+        }
+        self.index = self.positions.len();
+    }
+
+    fn back(&mut self, current: Position) -> Option<Position> {
+        if self.index == self.positions.len() {
+            // remember where we are so we can come forward again
+            if self.positions.last() != Some(&current) {
+                self.positions.push(current);
+            }
+            self.index = self.positions.len().saturating_sub(1);
+        }
+        if self.index == 0 {
+            return None;
+        }
+        self.index -= 1;
+        self.positions.get(self.index).cloned()
+    }
+
+    fn forward(&mut self) -> Option<Position> {
+        if self.index + 1 >= self.positions.len() {
+            return None;
+        }
+        self.index += 1;
+        self.positions.get(self.index).cloned()
+    }
+
+    fn clear(&mut self) {
+        self.positions.clear();
+        self.index = 0;
+    }
+}
+
+/// Where the cursor and the view were when a search started
+struct SearchStart {
+    cursor_position: Position,
+    offset: Position,
+}
+
+impl SearchStart {
+    ///
+    /// End the search: accepted (Enter) the cursor stays on the match, cancelled (Esc) it
+    /// goes back where it was, scrolled as it was. Returns the position to remember in the
+    /// jump list when an accepted search moved the cursor.
+    ///
+    fn finish(
+        &self,
+        accepted: bool,
+        cursor_position: &mut Position,
+        offset: &mut Position,
+    ) -> Option<Position> {
+        if !accepted {
+            *cursor_position = self.cursor_position.clone();
+            *offset = self.offset.clone();
+            return None;
+        }
+        if *cursor_position != self.cursor_position {
+            return Some(self.cursor_position.clone());
+        }
+        None
+    }
+}
+/// A copied or cut text
+struct Yank {
+    text: String,
+    // whole lines were copied, they are pasted as lines rather than at the cursor
+    linewise: bool,
+}
+
+/// The last copied and cut texts, the most recent one last
+struct YankRing {
+    entries: Vec<Yank>,
+    capacity: usize,
+}
+impl YankRing {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            capacity: capacity.max(1),
         }
     }
+
+    fn push(&mut self, text: String, linewise: bool) {
+        if text.is_empty() {
+            return;
+        }
+        self.entries.push(Yank { text, linewise });
+        if self.entries.len() > self.capacity {
+            self.entries.remove(0);
+        }
+    }
+
+    /// The entry `age` yanks back (0 is the latest), cycling through the ring
+    fn get(&self, age: usize) -> Option<&Yank> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let len = self.entries.len();
+        self.entries.get(len - 1 - age % len)
+    }
+}
+
+/// Where on the screen to bring the cursor's line, like Vim's `zt`, `zz` and `zb`
+#[derive(Clone, Copy)]
+enum LinePlacement {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// Where the last paste put its text, so yank-pop can replace it
+struct Paste {
+    start: Position,
+    end: Position,
+    age: usize,
 }
+
+/// What a command typed at the command prompt (Alt-:) does
 pub enum Command {
-    Execute(fn() -> bool),
+    /// Run an editor action, e.g. `:undo`
+    Execute(fn(&mut Editor)),
+    /// `:w`
     Save,
-    Search,
+    /// `:search`, prompting for the query unless it was given (`:search foo`, `:/foo`)
+    Search(Option<String>),
+    /// Nothing was typed
     Cancel,
+    /// `:q`, asking first when there are unsaved changes
     Quit,
+    /// `:q!`, dropping unsaved changes
+    ForceQuit,
+    /// `:wq` or `:x`
+    SaveAndQuit,
 }
 impl Editor {
     /// Default constructor, takes no argument and builds an Editor object.
     pub fn default() -> Self {
-        let args: Vec<String> = std::env::args().collect();
-        let document = if args.len() > 1 {
-            Document::open(&args[1]).unwrap_or_default()
+        let mut args: Vec<String> = std::env::args().collect();
+        let (options, option_warnings) = CliOptions::parse(&mut args);
+        let (config, mut warnings) = Config::load();
+        warnings.extend(option_warnings);
+        highlighting::set_theme(config.theme.clone());
+        let mut status_message = match warnings.first() {
+            Some(warning) if warnings.len() > 1 => StatusMessage::error(format!(
+                "{} (and {} more config problems)",
+                warning,
+                warnings.len() - 1
+            )),
+            Some(warning) => StatusMessage::error(warning.clone()),
+            None => StatusMessage::default(),
+        };
+        let mut document = if args.len() > 1 {
+            open_file(&args[1], &config, &options).unwrap_or_else(|error| {
+                status_message = StatusMessage::error(error.to_string());
+                new_document(&config, &options)
+            })
         } else {
-            Document::default()
+            new_document(&config, &options)
+        };
+        // files failing to load are reported rather than opened as empty buffers
+        let mut buffers = Vec::new();
+        if options.read_only {
+            document.set_read_only(true);
+        } else if document.is_read_only() {
+            status_message =
+                StatusMessage::error(format!("{} is very large, opened read-only", args[1]));
+        } else if !document.conflicts().is_empty() {
+            status_message = StatusMessage::info(format!(
+                "{} merge conflicts, Alt-M/Alt-Shift-M to move between them",
+                document.conflicts().len()
+            ));
+        }
+        for filename in args.iter().skip(2) {
+            match open_file(filename, &config, &options) {
+                Ok(mut document) => {
+                    if options.read_only {
+                        document.set_read_only(true);
+                    }
+                    buffers.push(Buffer {
+                        document,
+                        cursor_position: Position::default(),
+                        offset: Position::default(),
+                    })
+                }
+                Err(error) => status_message = StatusMessage::error(error.to_string()),
+            }
+        }
+        let terminal = Terminal::default().unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        });
+        if config.mouse {
+            terminal.enable_mouse();
+        }
+        let project_root = match args.get(1) {
+            Some(filename) => find_project_root(Path::new(filename)),
+            None => std::env::current_dir()
+                .ok()
+                .and_then(|dir| find_project_root(&dir)),
         };
         Self {
-            terminal: Terminal::default().expect("Error: Could not create terminal on device"),
+            terminal,
             should_quit: false,
             cursor_position: Position::default(),
             document: document,
             offset: Position::default(),
-            status_message: StatusMessage::default(),
+            status_message,
+            jump_list: JumpList::default(),
+            yank_ring: YankRing::new(config.yank_ring_size),
+            last_paste: None,
+            show_line_numbers: config.line_numbers,
+            regex_search: config.regex_search,
+            quit_times: config.quit_times,
+            config,
+            options,
+            selection: None,
+            selecting: false,
+            shaded_brackets: Vec::new(),
+            other_cursors: Vec::new(),
+            sanitized_chars: 0,
+            seen_revision: 0,
+            last_edit: Instant::now(),
+            rehighlight_row: None,
+            buffers,
+            active_buffer: 0,
+            closed_buffers: Vec::new(),
+            follow: false,
+            overwrite: false,
+            show_control: false,
+            show_char_info: false,
+            project_root,
+            highlighters: HashMap::new(),
+            goal_column: None,
+            pending_count: None,
+            surround_pending: false,
+        }
+    }
+
+    ///
+    /// Highlight the files of the given filetype (as named in the status bar, e.g. "Rust")
+    /// with `highlighter`, including the files already open.
+    ///
+    pub fn with_highlighter(
+        mut self,
+        filetype: &str,
+        highlighter: impl Highlighter + 'static,
+    ) -> Self {
+        let highlighter: Rc<dyn Highlighter> = Rc::new(highlighter);
+        self.highlighters
+            .insert(filetype.to_string(), Rc::clone(&highlighter));
+        let documents = std::iter::once(&mut self.document)
+            .chain(self.buffers.iter_mut().map(|buffer| &mut buffer.document));
+        for document in documents {
+            if document.filetype.name() == filetype {
+                document.set_highlighter(Rc::clone(&highlighter));
+            }
+        }
+        self
+    }
+
+    /// Open a file, highlighted by the highlighter registered for its filetype if any
+    fn open_document(&self, filename: &str) -> Result<Document, EditorError> {
+        let document = open_file(filename, &self.config, &self.options)?;
+        match self.highlighters.get(&document.filetype.name()) {
+            Some(highlighter) => Ok(document.with_highlighter(Rc::clone(highlighter))),
+            None => Ok(document),
         }
     }
 
     /// Runs an editor on the console.
     pub fn run(&mut self) {
         loop {
+            // crossterm doesn't report resizes, the size is checked before every redraw
+            if self.terminal.update_size() {
+                self.terminal.clear_screen();
+                self.scroll();
+            }
+            self.layout_bars();
+            self.shade_brackets();
             if let Err(error) = self.refresh_screen() {
                 let _ = self.clear_screen();
                 self.die(error, 1);
@@ -81,6 +550,8 @@ impl Editor {
             if self.should_quit {
                 break;
             }
+            self.reload_changed_file();
+            self.rehighlight_when_idle();
             if let Err(error) = self.process_input() {
                 let _ = self.clear_screen();
                 self.die(error, 1);
@@ -88,64 +559,518 @@ impl Editor {
         }
     }
 
-    /// Process any key pressed by the user on the console
+    ///
+    /// Process any key pressed by the user on the console, along with every key already
+    /// waiting behind it, so held keys and pastes cost a single redraw
+    ///
     pub fn process_input(&mut self) -> Result<(), std::io::Error> {
-        let key_pressed = self.terminal.read_keypress();
-        if let Some(event) = key_pressed {
-            //     self.document.insert(event, &self.cursor_position);
-            //     self.move_cursor(KeyEvent::Right);
-            // }
+        // wake up now and then to notice changes on disk
+        let mut key_pressed = self
+            .terminal
+            .read_keypress_timeout(Duration::from_millis(500));
+        let mut processed = 0;
+        // the wheel scrolls away from the cursor, keys bring it back in view
+        let mut follow_cursor = true;
+        while let Some(event) = key_pressed {
             match event {
-                InputEvent::Keyboard(key) => match key {
-                    KeyEvent::Char(e) => {
-                        self.document.insert(e, &self.cursor_position);
-                        self.move_cursor(KeyEvent::Right)
-                    }
-                    KeyEvent::Ctrl('Q') => {
-                        let _ = self.quit()?;
-                    }
-                    KeyEvent::Ctrl('S') => {
-                        let _ = self.save()?;
-                    }
-                    KeyEvent::Ctrl('F') => {
-                        self.search();
-                    }
-                    KeyEvent::Enter => {
-                        self.document.insert('\n', &self.cursor_position);
-                        self.move_cursor(KeyEvent::Down);
-                        self.move_cursor(KeyEvent::Home);
-                    }
-                    KeyEvent::Tab => {
-                        self.document.insert('\t', &self.cursor_position);
-                        self.move_cursor(KeyEvent::Right)
-                    }
+                InputEvent::Keyboard(key) => {
+                    self.handle_key(key)?;
+                    self.scroll();
+                    follow_cursor = true;
+                }
+                InputEvent::Mouse(event) => {
+                    follow_cursor = self.handle_mouse(event);
+                }
+                _ => (),
+            }
+            processed += 1;
+            // keep the screen alive under a flood of input
+            if self.should_quit || processed >= MAX_KEYS_PER_REDRAW {
+                break;
+            }
+            key_pressed = self.terminal.poll_keypress();
+        }
+        if self.sanitized_chars > 0 {
+            self.status_message =
+                StatusMessage::info(format!("{} characters normalized", self.sanitized_chars));
+            self.sanitized_chars = 0;
+        }
+        if follow_cursor {
+            self.scroll();
+        }
+        Ok(())
+    }
+
+    ///
+    /// Place the cursor where the text is clicked, scroll with the wheel leaving the cursor be.
+    /// Returns whether the view should follow the cursor.
+    ///
+    fn handle_mouse(&mut self, event: MouseEvent) -> bool {
+        match event {
+            MouseEvent::Press(MouseButton::Left, column, row) => {
+                // crossterm reports the cells counted from one
+                let row = (row as usize).saturating_sub(1);
+                let column = (column as usize).saturating_sub(1);
+                let text_row = match row.checked_sub(self.text_top()) {
+                    Some(text_row) if text_row < self.terminal.height() => text_row,
+                    // a click on the bars
+                    _ => return false,
+                };
+                if self.document.is_empty() {
+                    return false;
+                }
+                let y = self.offset.y.saturating_add(text_row).min(self.last_line());
+                let column = column
+                    .saturating_sub(self.gutter_width())
+                    .saturating_add(self.offset.x);
+                let tab_width = self.document.tab_width();
+                let x = self
+                    .document
+                    .row(y)
+                    .map_or(0, |row| row.index_at_column(column, tab_width));
+                self.clear_selection();
+                self.collapse_cursors();
+                self.goal_column = None;
+                self.cursor_position = Position { x, y };
+                true
+            }
+            MouseEvent::Press(MouseButton::WheelUp, _, _) => {
+                self.offset.y = self.offset.y.saturating_sub(WHEEL_LINES);
+                false
+            }
+            MouseEvent::Press(MouseButton::WheelDown, _, _) => {
+                self.offset.y = self
+                    .offset
+                    .y
+                    .saturating_add(WHEEL_LINES)
+                    .min(self.last_line());
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle a key from the keyboard: dismiss errors, collect repeat counts, act on the key
+    fn handle_key(&mut self, key: KeyEvent) -> Result<(), std::io::Error> {
+        // keys remapped in the config act as the key they're bound to
+        let key = self.config.key_bindings.get(&key).cloned().unwrap_or(key);
+        // errors stay until dismissed by the next key
+        if self.status_message.severity == Severity::Error {
+            self.status_message = StatusMessage::default();
+        }
+        if self.surround_pending {
+            self.surround_pending = false;
+            match key {
+                KeyEvent::Char(c) if closing_pair(c).is_some() => self.surround_word(c),
+                _ => self.status_message = StatusMessage::default(),
+            }
+        } else if let KeyEvent::Alt(digit @ '0'..='9') = key {
+            self.push_count_digit(digit);
+        } else {
+            let count = if is_repeatable(key) {
+                self.pending_count.take().unwrap_or(1)
+            } else {
+                self.pending_count = None;
+                1
+            };
+            for _ in 0..count {
+                self.process_key(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Act on a single key press
+    fn process_key(&mut self, key: KeyEvent) -> Result<(), std::io::Error> {
+        // the presses quitting a dirty buffer must follow each other
+        if key != KeyEvent::Ctrl('Q') {
+            self.quit_times = self.config.quit_times;
+        }
+        if !is_vertical_move(key) {
+            self.goal_column = None;
+        }
+        match key {
+            KeyEvent::Ctrl('V') | KeyEvent::Alt('V') | KeyEvent::Alt('y') => (),
+            _ => self.last_paste = None,
+        }
+        if self.document.is_read_only() && is_edit(key) {
+            self.status_message = StatusMessage::info("The buffer is read-only".to_string());
+            return Ok(());
+        }
+        if !self.other_cursors.is_empty() {
+            match key {
+                KeyEvent::Char(_) | KeyEvent::Backspace | KeyEvent::Delete => {
+                    self.edit_all_cursors(key);
+                    return Ok(());
+                }
+                KeyEvent::Ctrl('D') if self.selection.is_some() => (),
+                _ => self.collapse_cursors(),
+            }
+        }
+        match key {
+            KeyEvent::Char(e) if self.selection.is_some() && closing_pair(e).is_some() => {
+                self.surround_selection(e);
+            }
+            KeyEvent::Alt('a') => {
+                self.strip_ansi();
+            }
+            KeyEvent::Alt('d') => {
+                self.duplicate_selection();
+            }
+            KeyEvent::Alt('q') => {
+                self.surround_pending = true;
+                self.status_message =
+                    StatusMessage::info("Surround word with: ( [ { < \" ' `".to_string());
+            }
+            KeyEvent::Alt('k') => {
+                self.swap_line(KeyEvent::Up);
+            }
+            KeyEvent::Alt('j') => {
+                self.swap_line(KeyEvent::Down);
+            }
+            KeyEvent::Alt('w') => {
+                self.wrap_lines();
+            }
+            KeyEvent::Alt('e') => {
+                self.export_html();
+            }
+            KeyEvent::Alt('v') => {
+                self.show_diff();
+            }
+            KeyEvent::Alt('f') => {
+                self.toggle_follow();
+            }
+            KeyEvent::Alt('n') => {
+                self.search_again(SearchDirection::Forward);
+            }
+            KeyEvent::Alt('N') => {
+                self.search_again(SearchDirection::Backward);
+            }
+            KeyEvent::Alt('c') => {
+                self.number_column();
+            }
+            KeyEvent::Alt('=') => {
+                self.align_selection();
+            }
+            KeyEvent::Alt('t') => {
+                self.place_cursor_line(LinePlacement::Top);
+            }
+            KeyEvent::Alt('z') => {
+                self.place_cursor_line(LinePlacement::Center);
+            }
+            KeyEvent::Alt('b') => {
+                self.place_cursor_line(LinePlacement::Bottom);
+            }
+            KeyEvent::Alt('l') => {
+                let removed = self.document.remove_leading_blank_lines();
+                self.report_removed_lines(&removed);
+            }
+            KeyEvent::Alt('L') => {
+                let removed = self.document.remove_trailing_blank_lines();
+                self.report_removed_lines(&removed);
+            }
+            KeyEvent::Alt('s') => {
+                let removed = self.document.squeeze_blank_lines();
+                self.report_removed_lines(&removed);
+            }
+            KeyEvent::Alt('m') => {
+                self.goto_conflict(SearchDirection::Forward);
+            }
+            KeyEvent::Alt('M') => {
+                self.goto_conflict(SearchDirection::Backward);
+            }
+            KeyEvent::Alt('O') => {
+                self.resolve_conflict(Resolution::Ours);
+            }
+            KeyEvent::Alt('T') => {
+                self.resolve_conflict(Resolution::Theirs);
+            }
+            KeyEvent::Alt('B') => {
+                self.resolve_conflict(Resolution::Both);
+            }
+            KeyEvent::Alt('h') => {
+                self.show_checksum();
+            }
+            KeyEvent::Alt('R') => {
+                self.replace_all();
+            }
+            KeyEvent::Alt('g') => {
+                self.goto_long_line();
+            }
+            KeyEvent::Alt('E') => {
+                self.convert_line_endings();
+            }
+            KeyEvent::Alt('r') => {
+                self.reopen_closed_buffer();
+            }
+            KeyEvent::Alt('u') => {
+                self.show_char_info = !self.show_char_info;
+            }
+            KeyEvent::Ctrl('R') => {
+                let read_only = !self.document.is_read_only();
+                self.document.set_read_only(read_only);
+                self.status_message = StatusMessage::info(if read_only {
+                    "The buffer is read-only".to_string()
+                } else {
+                    "The buffer is editable".to_string()
+                });
+            }
+            KeyEvent::Ctrl('K') => {
+                self.clear_selection();
+                self.document.delete_to_eol(&self.cursor_position);
+            }
+            KeyEvent::Alt('K') => {
+                self.clear_selection();
+                self.document.delete_line(self.cursor_position.y);
+                let y = self.cursor_position.y.min(self.last_line());
+                self.jump_to(Position { x: 0, y });
+            }
+            KeyEvent::Alt('x') => {
+                self.regex_search = !self.regex_search;
+                self.status_message = StatusMessage::info(if self.regex_search {
+                    "Searching with regular expressions".to_string()
+                } else {
+                    "Searching for plain text".to_string()
+                });
+            }
+            KeyEvent::Alt('i') => {
+                self.show_control = !self.show_control;
+                self.status_message = StatusMessage::info(if self.show_control {
+                    "Showing control characters".to_string()
+                } else {
+                    "Hiding control characters".to_string()
+                });
+            }
+            // pastes reach us as typed characters
+            KeyEvent::Char(e)
+                if self.config.sanitize_paste
+                    && self.config.paste_substitutions.contains_key(&e) =>
+            {
+                let replacement = self.config.paste_substitutions[&e].clone();
+                self.delete_selection();
+                for c in replacement.chars() {
+                    self.type_char(c);
+                }
+                self.sanitized_chars += 1;
+            }
+            KeyEvent::Char(e) => {
+                self.type_char(e);
+            }
+            KeyEvent::Ctrl('Q') => {
+                self.run_command(Command::Quit)?;
+            }
+            KeyEvent::Ctrl('S') => {
+                self.run_command(Command::Save)?;
+            }
+            KeyEvent::Ctrl('F') => {
+                self.run_command(Command::Search(None))?;
+            }
+            KeyEvent::Alt(':') => {
+                self.command_prompt()?;
+            }
+            KeyEvent::Ctrl('H') => {
+                self.replace();
+            }
+            KeyEvent::Insert => {
+                self.overwrite = !self.overwrite;
+            }
+            KeyEvent::Ctrl('A') => {
+                self.select_all();
+            }
+            KeyEvent::Ctrl('D') => {
+                self.select_next_occurrence();
+            }
+            KeyEvent::Ctrl('C') => {
+                self.copy_selection();
+            }
+            KeyEvent::Ctrl('X') => {
+                self.cut_selection();
+            }
+            KeyEvent::Ctrl('V') => {
+                self.paste();
+            }
+            KeyEvent::Alt('V') => {
+                self.paste_as_lines();
+            }
+            KeyEvent::Alt('y') => {
+                self.yank_pop();
+            }
+            KeyEvent::Ctrl('Z') => {
+                self.undo_redo(true);
+            }
+            KeyEvent::Ctrl('L') => {
+                self.show_line_numbers = !self.show_line_numbers;
+                self.scroll();
+            }
+            KeyEvent::Ctrl('Y') => {
+                self.undo_redo(false);
+            }
+            // Vim's `~`
+            KeyEvent::Ctrl('E') => {
+                if let Some(len) = self.document.toggle_case(&self.cursor_position) {
+                    let width = self
+                        .document
+                        .row(self.cursor_position.y)
+                        .map_or(0, |row| row.len());
+                    self.cursor_position.x = (self.cursor_position.x + len).min(width);
+                }
+            }
+            KeyEvent::Ctrl('G') => {
+                self.goto_line();
+            }
+            // Ctrl-/ reaches us as Ctrl-_ (both send 0x1F)
+            KeyEvent::Ctrl('_') => {
+                self.toggle_comment();
+            }
+            KeyEvent::Ctrl('T') => {
+                self.open_related_file();
+            }
+            KeyEvent::Ctrl('N') => {
+                self.switch_buffer((self.active_buffer + 1) % (self.buffers.len() + 1));
+            }
+            KeyEvent::Ctrl('P') => {
+                let count = self.buffers.len() + 1;
+                self.switch_buffer((self.active_buffer + count - 1) % count);
+            }
+            KeyEvent::Ctrl('W') => {
+                self.close_buffer();
+            }
+            KeyEvent::Ctrl('O') => {
+                self.jump_back();
+            }
+            // Ctrl-I (Vim's forward jump) reaches us as a Tab
+            KeyEvent::Ctrl('U') => {
+                self.jump_forward();
+            }
+            KeyEvent::Enter => {
+                self.delete_selection();
+                self.document.insert('\n', &self.cursor_position);
+                self.move_cursor(KeyEvent::Down);
+                self.move_cursor(KeyEvent::Home);
+            }
+            KeyEvent::Tab if self.config.smart_tab => {
+                self.smart_tab();
+            }
+            KeyEvent::Tab => {
+                self.delete_selection();
+                let indent = self.document.indent_unit();
+                self.document.insert_text(&indent, &self.cursor_position);
+                for _ in indent.chars() {
+                    self.move_cursor(KeyEvent::Right);
+                }
+            }
 
-                    KeyEvent::Backspace => {
-                        if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
+            KeyEvent::Backspace => {
+                if !self.delete_selection()
+                    && (self.cursor_position.x > 0 || self.cursor_position.y > 0)
+                {
+                    let empty_pair = self.in_empty_pair();
+                    if self.config.smart_backspace {
+                        // the last one is deleted as usual below
+                        for _ in 1..self.indent_to_remove() {
                             self.move_cursor(KeyEvent::Left);
                             self.document.delete(&self.cursor_position);
                         }
                     }
-                    KeyEvent::Delete => {
+                    self.move_cursor(KeyEvent::Left);
+                    self.document.delete(&self.cursor_position);
+                    if empty_pair {
                         self.document.delete(&self.cursor_position);
                     }
-                    KeyEvent::Left
-                    | KeyEvent::Right
-                    | KeyEvent::Up
-                    | KeyEvent::Down
-                    | KeyEvent::PageDown
-                    | KeyEvent::PageUp
-                    | KeyEvent::Home
-                    | KeyEvent::End => self.move_cursor(key),
-                    _ => (),
-                },
-                _ => (),
+                }
+            }
+            KeyEvent::Delete => {
+                if !self.delete_selection() {
+                    self.document.delete(&self.cursor_position);
+                }
+            }
+            KeyEvent::Left
+            | KeyEvent::Right
+            | KeyEvent::Up
+            | KeyEvent::Down
+            | KeyEvent::PageDown
+            | KeyEvent::PageUp
+            | KeyEvent::Home
+            | KeyEvent::End
+            | KeyEvent::CtrlLeft
+            | KeyEvent::CtrlRight => {
+                // the selection mode ends along with its selection (cut, deleted...)
+                self.selecting = self.selecting && self.selection.is_some();
+                if self.selecting {
+                    self.extend_selection(key);
+                } else {
+                    self.clear_selection();
+                    self.move_cursor(key);
+                }
+            }
+            KeyEvent::Ctrl('B') => {
+                self.selecting = !self.selecting;
+                if self.selecting {
+                    self.clear_selection();
+                    self.selection = Some(Selection {
+                        anchor: self.cursor_position.clone(),
+                        head: self.cursor_position.clone(),
+                    });
+                    self.status_message = StatusMessage::info(
+                        "Selecting, move to extend (Ctrl-B or ESC to stop)".to_string(),
+                    );
+                } else {
+                    self.clear_selection();
+                    self.status_message = StatusMessage::default();
+                }
+            }
+            KeyEvent::Esc if self.selecting => {
+                self.selecting = false;
+                self.clear_selection();
+                self.status_message = StatusMessage::default();
             }
+            // crossterm does not report shift + home/end
+            KeyEvent::ShiftLeft => self.extend_selection(KeyEvent::Left),
+            KeyEvent::ShiftRight => self.extend_selection(KeyEvent::Right),
+            KeyEvent::ShiftUp => self.extend_selection(KeyEvent::Up),
+            KeyEvent::ShiftDown => self.extend_selection(KeyEvent::Down),
+            _ => (),
         }
-        self.scroll();
         Ok(())
     }
 
+    ///
+    /// Once the document has gone unedited for the configured time, highlight it all again,
+    /// a chunk of rows per call so a key press never waits long
+    ///
+    fn rehighlight_when_idle(&mut self) {
+        let interval = match self.config.idle_rehighlight {
+            Some(interval) => interval,
+            None => return,
+        };
+        let revision = self.document.revision();
+        if revision != self.seen_revision {
+            self.seen_revision = revision;
+            self.last_edit = Instant::now();
+            self.rehighlight_row = Some(0);
+            return;
+        }
+        let row = match self.rehighlight_row {
+            Some(row) if self.last_edit.elapsed() >= interval => row,
+            _ => return,
+        };
+        self.rehighlight_row = self.document.rehighlight_from(row, IDLE_REHIGHLIGHT_ROWS);
+        // the selection shading went with the old highlighting
+        self.shade_selection(None);
+    }
+
+    /// Grow the pending repeat count (Alt + digits) applied to the next key
+    fn push_count_digit(&mut self, digit: char) {
+        let value = digit.to_digit(10).unwrap_or(0) as usize;
+        let count = self
+            .pending_count
+            .unwrap_or(0)
+            .saturating_mul(10)
+            .saturating_add(value)
+            .min(MAX_REPEAT_COUNT);
+        self.pending_count = Some(count);
+    }
+
     fn refresh_screen(&self) -> Result<(), std::io::Error> {
         self.terminal.cursor_hide();
         self.terminal.cursor_position(&Position::default());
@@ -154,11 +1079,20 @@ impl Editor {
             println!("Goodbye...");
         } else {
             self.draw_rows();
+            self.draw_match_ticks();
+            self.draw_expanded_message();
             self.draw_status_bar();
             self.draw_message_bar();
             self.terminal.cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
+                x: self
+                    .cursor_render_x()
+                    .saturating_sub(self.offset.x)
+                    .saturating_add(self.gutter_width()),
+                y: self
+                    .cursor_position
+                    .y
+                    .saturating_sub(self.offset.y)
+                    .saturating_add(self.text_top()),
             });
         }
         self.terminal.cursor_show();
@@ -175,33 +1109,119 @@ impl Editor {
         Ok(())
     }
 
+    /// The screen row where the text starts, below the bars when they are at the top
+    fn text_top(&self) -> usize {
+        if self.config.status_bar_top {
+            self.terminal.bar_rows()
+        } else {
+            0
+        }
+    }
+
+    /// Screen rows of the (first row of the) status bar and of the message bar
+    fn bar_rows(&self) -> (usize, usize) {
+        let status_lines = self.terminal.bar_rows().saturating_sub(1);
+        if self.config.status_bar_top {
+            (0, status_lines)
+        } else {
+            let height = self.terminal.height();
+            (height, height.saturating_add(status_lines))
+        }
+    }
+
     fn draw_rows(&self) {
         let height = self.terminal.height();
+        let top = self.text_top();
         for terminal_row in 0..height {
+            // each row is placed explicitly, a newline after the bottom one would scroll the screen
+            self.terminal.cursor_position(&Position {
+                x: 0,
+                y: top + terminal_row,
+            });
             self.terminal.clear_current_line();
             if let Some(row) = self
                 .document
                 .row(self.offset.y.saturating_add(terminal_row))
             {
-                self.draw_row(row);
+                self.draw_row(row, self.offset.y.saturating_add(terminal_row));
             } else if self.document.is_empty() && terminal_row == height / 3 {
                 self.draw_welcome_message();
             } else {
-                println!("~\r");
+                print!("~\r");
             }
         }
     }
-    fn draw_row(&self, row: &Row) {
-        let width = self.terminal.width();
+    fn draw_row(&self, row: &Row, y: usize) {
+        let gutter = self.gutter_width();
+        if gutter > 0 {
+            print!(
+                "{}{:>width$} {}",
+                crossterm::SetFg(Color::DarkGrey),
+                y + 1,
+                crossterm::SetFg(Color::Reset),
+                width = gutter - 1
+            );
+        }
+        let width = self.text_width();
         let start = self.offset.x;
         let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
+        let row = row.render(start, end, self.show_control, self.document.tab_width());
+        print!("{}\r", row);
+    }
+
+    /// Columns taken by the line numbers: enough digits for the last line and a space
+    fn gutter_width(&self) -> usize {
+        if !self.show_line_numbers {
+            return 0;
+        }
+        self.document.len().max(1).to_string().len() + 1
+    }
+
+    /// Columns left for the text, right of the gutter
+    fn text_width(&self) -> usize {
+        self.terminal.width().saturating_sub(self.gutter_width())
+    }
+
+    /// Tick the right edge where the highlighted search matches, like a scrollbar of the whole document
+    fn draw_match_ticks(&self) {
+        let query = match &self.document.search_string {
+            Some(query) if self.config.search_match_ticks => query,
+            _ => return,
+        };
+        let height = self.terminal.height();
+        let width = self.terminal.width();
+        let len = self.document.len();
+        if height == 0 || width == 0 || len == 0 {
+            return;
+        }
+        let mut ticks: Vec<usize> = self
+            .document
+            .match_rows(query)
+            .iter()
+            .map(|&y| y * height / len)
+            .collect();
+        ticks.dedup();
+        self.terminal
+            .set_fg_color(highlighting::Type::Match.to_color());
+        for row in ticks {
+            self.terminal.cursor_position(&Position {
+                x: width - 1,
+                y: self.text_top() + row,
+            });
+            print!("▐");
+        }
+        self.terminal.reset_fg_color();
+    }
+
+    /// The last line the cursor may sit on.
+    /// Only an empty document lets the cursor on the (virtual) line past its end.
+    fn last_line(&self) -> usize {
+        self.document.len().saturating_sub(1)
     }
 
     fn move_cursor(&mut self, key: KeyEvent) {
         let Position { mut x, mut y } = self.cursor_position;
-        let doc_len = self.document.len();
+        let last_line = self.last_line();
         let height = self.terminal.height();
         let mut width = if let Some(row) = self.document.row(y) {
             row.len()
@@ -211,7 +1231,7 @@ impl Editor {
         match key {
             KeyEvent::Up => y = y.saturating_sub(1),
             KeyEvent::Down => {
-                if y < doc_len {
+                if y < last_line {
                     y = y.saturating_add(1);
                 }
             }
@@ -230,18 +1250,56 @@ impl Editor {
             KeyEvent::Right => {
                 if x < width {
                     x += 1;
-                } else if y < doc_len {
+                } else if y < last_line {
                     y += 1;
                     x = 0;
                 }
             }
-            KeyEvent::PageDown => y = y.saturating_add(height),
+            // words end where the tokenizer splits them, at whitespace and punctuation
+            KeyEvent::CtrlLeft => {
+                if x > 0 {
+                    let chars: Vec<char> = self
+                        .document
+                        .row(y)
+                        .map_or(Vec::new(), |row| row.text().chars().take(x).collect());
+                    let is_word = |c: &char| !highlighting::is_separator(*c);
+                    let gap = chars.iter().rev().take_while(|c| !is_word(c)).count();
+                    x -= gap;
+                    x -= chars[..x].iter().rev().take_while(|c| is_word(c)).count();
+                } else if y > 0 {
+                    y -= 1;
+                    x = self.document.row(y).map_or(0, |row| row.len());
+                }
+            }
+            KeyEvent::CtrlRight => {
+                if x < width {
+                    let chars: Vec<char> = self
+                        .document
+                        .row(y)
+                        .map_or(Vec::new(), |row| row.text().chars().skip(x).collect());
+                    let is_word = |c: &char| !highlighting::is_separator(*c);
+                    let word = chars.iter().take_while(|c| is_word(c)).count();
+                    x += word;
+                    x += chars[word..].iter().take_while(|c| !is_word(c)).count();
+                } else if y < last_line {
+                    y += 1;
+                    x = 0;
+                }
+            }
+            KeyEvent::PageDown => y = y.saturating_add(height).min(last_line),
             KeyEvent::PageUp => y = y.saturating_sub(height),
             KeyEvent::End => x = width,
             KeyEvent::Home => x = 0,
             _ => (),
         }
 
+        if is_vertical_move(key) {
+            // remember the column we started from, ragged lines shouldn't lose it
+            let goal = *self.goal_column.get_or_insert(x);
+            x = goal;
+        } else {
+            self.goal_column = None;
+        }
         width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -254,10 +1312,848 @@ impl Editor {
         // self.terminal.cursor_position(&self.cursor_position);
     }
 
-    fn scroll(&mut self) {
+    /// Move the cursor, growing the selection from where it started (or from the cursor)
+    fn extend_selection(&mut self, key: KeyEvent) {
+        let previous = self.selection.clone();
+        let anchor = match &previous {
+            Some(selection) => selection.anchor.clone(),
+            None => self.cursor_position.clone(),
+        };
+        self.move_cursor(key);
+        self.selection = Some(Selection {
+            anchor,
+            head: self.cursor_position.clone(),
+        });
+        self.shade_selection(previous);
+    }
+
+    /// Select the whole document, the cursor moving to its end
+    fn select_all(&mut self) {
+        let previous = self.selection.take();
+        if !self.document.is_empty() {
+            let y = self.last_line();
+            let x = self.document.row(y).map_or(0, |row| row.len());
+            self.cursor_position = Position { x, y };
+            self.selection = Some(Selection {
+                anchor: Position::default(),
+                head: self.cursor_position.clone(),
+            });
+        }
+        self.shade_selection(previous);
+    }
+    ///
+    /// Select the word under the cursor, or add the next occurrence of the selected text
+    /// as a new selection (wrapping at the end of the document). Typing then edits them all.
+    ///
+    fn select_next_occurrence(&mut self) {
+        let previous = self.selection.clone();
+        let selection = match previous.clone() {
+            Some(selection) => selection,
+            None => {
+                match self.word_at_cursor() {
+                    Some(selection) => {
+                        self.cursor_position = selection.head.clone();
+                        self.selection = Some(selection);
+                    }
+                    None => {
+                        self.status_message =
+                            StatusMessage::info("No word under the cursor".to_string());
+                    }
+                }
+                self.shade_selection(previous);
+                return;
+            }
+        };
+        let (start, end) = selection.range();
+        if start.y != end.y || start == end {
+            self.status_message =
+                StatusMessage::info("Select some text on a single line first".to_string());
+            return;
+        }
+        let text = self.document.text_range(&start, &end);
+        let len = end.x - start.x;
+        let overlaps = |other: &Selection, x: usize, y: usize| {
+            let (other_start, other_end) = other.range();
+            other_start.y == y && x < other_end.x && other_start.x < x + len
+        };
+        let found = match self.document.find_occurrence(&text, &end) {
+            Some(found) => found,
+            None => return,
+        };
+        if overlaps(&selection, found.x, found.y)
+            || self
+                .other_cursors
+                .iter()
+                .any(|other| overlaps(other, found.x, found.y))
+        {
+            self.status_message = StatusMessage::info("All occurrences are selected".to_string());
+            return;
+        }
+        // the newest occurrence becomes the main selection, so the view follows it
+        self.other_cursors.push(selection);
+        let head = Position {
+            x: found.x + len,
+            y: found.y,
+        };
+        self.cursor_position = head.clone();
+        self.selection = Some(Selection {
+            anchor: found,
+            head,
+        });
+        self.shade_selection(previous);
+        self.status_message = StatusMessage::info(format!(
+            "{} occurrences selected",
+            self.other_cursors.len() + 1
+        ));
+    }
+
+    /// The word (letters, digits and underscores) the cursor is in or right after
+    fn word_at_cursor(&self) -> Option<Selection> {
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.width();
-        let height = self.terminal.height();
+        let chars: Vec<char> = self.document.row(y)?.text().chars().collect();
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let x = x.min(chars.len());
+        let start = x - chars[..x].iter().rev().take_while(|c| is_word(c)).count();
+        let end = x + chars[x..].iter().take_while(|c| is_word(c)).count();
+        if start == end {
+            return None;
+        }
+        Some(Selection {
+            anchor: Position { x: start, y },
+            head: Position { x: end, y },
+        })
+    }
+
+    ///
+    /// Apply a typed character, Backspace or Delete at every cursor: selections are replaced,
+    /// bare cursors edit next to them. Edits never cross a line end so rows stay put.
+    ///
+    fn edit_all_cursors(&mut self, key: KeyEvent) {
+        let main = match self.selection.take() {
+            Some(selection) => selection,
+            None => Selection {
+                anchor: self.cursor_position.clone(),
+                head: self.cursor_position.clone(),
+            },
+        };
+        let mut cursors: Vec<(bool, Position, Position)> = self
+            .other_cursors
+            .drain(..)
+            .map(|selection| (false, selection))
+            .chain(std::iter::once((true, main)))
+            .map(|(is_main, selection)| {
+                let (start, end) = selection.range();
+                (is_main, start, end)
+            })
+            .collect();
+        cursors.sort_by_key(|(_, start, _)| (start.y, start.x));
+
+        // edits shift the cursors following them on the same row
+        let mut shift: HashMap<usize, isize> = HashMap::new();
+        self.document.begin_batch();
+        for (is_main, start, end) in cursors {
+            let offset = shift.get(&start.y).cloned().unwrap_or(0);
+            let mut position = Position {
+                x: (start.x as isize + offset) as usize,
+                y: start.y,
+            };
+            let selected = end.x - start.x;
+            let width = self.document.row(position.y).map_or(0, |row| row.len());
+            let mut delta = 0;
+            if selected > 0 {
+                let end = Position {
+                    x: position.x + selected,
+                    y: position.y,
+                };
+                self.document.delete_range(&position, &end);
+                delta -= selected as isize;
+            }
+            match key {
+                KeyEvent::Char(c) => {
+                    self.document.insert(c, &position);
+                    position.x += 1;
+                    delta += 1;
+                }
+                KeyEvent::Backspace if selected == 0 && position.x > 0 => {
+                    position.x -= 1;
+                    self.document.delete(&position);
+                    delta -= 1;
+                }
+                KeyEvent::Delete if selected == 0 && position.x < width => {
+                    self.document.delete(&position);
+                    delta -= 1;
+                }
+                _ => (),
+            }
+            *shift.entry(start.y).or_insert(0) += delta;
+            if is_main {
+                self.cursor_position = position;
+            } else {
+                self.other_cursors.push(Selection {
+                    anchor: position.clone(),
+                    head: position,
+                });
+            }
+        }
+        self.document.end_batch();
+        self.shade_selection(None);
+    }
+
+    /// Drop the other cursors, keeping the main one
+    fn collapse_cursors(&mut self) {
+        let rows: Vec<usize> = self
+            .other_cursors
+            .drain(..)
+            .map(|selection| selection.head.y)
+            .collect();
+        for y in rows {
+            self.document.highlight_rows(y, y);
+        }
+        let selection = self.selection.clone();
+        self.shade_selection(selection);
+    }
+
+    fn clear_selection(&mut self) {
+        if let Some(previous) = self.selection.take() {
+            self.shade_selection(Some(previous));
+        }
+    }
+
+    /// Delete the selected text, leaving the cursor where it started.
+    /// Returns whether there was a selection to delete.
+    fn delete_selection(&mut self) -> bool {
+        match self.selection.take() {
+            Some(selection) => {
+                let (start, end) = selection.range();
+                self.document.delete_range(&start, &end);
+                self.cursor_position = start;
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Swap the current line with the one above (`Up`) or below (`Down`).
+    /// The cursor stays where it is on screen unless configured to follow its line.
+    ///
+    fn swap_line(&mut self, key: KeyEvent) {
+        let y = self.cursor_position.y;
+        let other = match key {
+            KeyEvent::Up => match y.checked_sub(1) {
+                Some(other) => other,
+                None => return,
+            },
+            KeyEvent::Down => y + 1,
+            _ => return,
+        };
+        if !self.document.swap_rows(y, other) {
+            return;
+        }
+        self.clear_selection();
+        if self.config.swap_lines_follow_text {
+            self.cursor_position.y = other;
+        }
+        let width = self
+            .document
+            .row(self.cursor_position.y)
+            .map_or(0, |row| row.len());
+        self.cursor_position.x = self.cursor_position.x.min(width);
+    }
+
+    ///
+    /// Copy the selection, or the current line when nothing is selected.
+    /// Whole lines (the current one, or a selection from a line start to another) are line-wise.
+    ///
+    fn copy_selection(&mut self) {
+        // copying ends the selection mode, the selection itself stays shown
+        self.selecting = false;
+        let (start, end) = match &self.selection {
+            Some(selection) => selection.range(),
+            None if self.cursor_position.y < self.document.len() => {
+                let y = self.cursor_position.y;
+                (Position { x: 0, y }, Position { x: 0, y: y + 1 })
+            }
+            None => return,
+        };
+        let linewise = start.x == 0 && end.x == 0 && end.y > start.y;
+        let mut text = self.document.text_range(&start, &end);
+        if linewise && !text.ends_with('\n') {
+            // the last line of the document has no line after it
+            text.push('\n');
+        }
+        self.status_message = StatusMessage::info(if linewise {
+            format!("Copied {} lines", end.y - start.y)
+        } else {
+            format!("Copied {} characters", text.chars().count())
+        });
+        if self.config.system_clipboard {
+            if let Err(error) = clipboard::copy(&text) {
+                self.status_message = StatusMessage::error(error.to_string());
+            }
+        }
+        self.yank_ring.push(text, linewise);
+    }
+
+    fn cut_selection(&mut self) {
+        // unlike copying, cutting needs a selection
+        if self.selection.is_none() {
+            return;
+        }
+        self.copy_selection();
+        if self.delete_selection() {
+            self.scroll();
+        }
+    }
+
+    ///
+    /// Insert the latest copied text, replacing the selection.
+    /// Line-wise copies go below the current line instead, unless replacing a selection.
+    ///
+    fn paste(&mut self) {
+        if self.config.system_clipboard {
+            self.take_system_clipboard();
+        }
+        let (text, linewise) = match self.yank_ring.get(0) {
+            Some(yank) => (yank.text.clone(), yank.linewise),
+            None => {
+                self.status_message = StatusMessage::info("Nothing to paste".to_string());
+                return;
+            }
+        };
+        if linewise && self.selection.is_none() {
+            self.paste_lines(&text);
+            return;
+        }
+        self.delete_selection();
+        let start = self.cursor_position.clone();
+        let end = self.document.insert_text(&text, &start);
+        self.cursor_position = end.clone();
+        self.last_paste = Some(Paste { start, end, age: 0 });
+    }
+
+    /// Make text copied in another program the latest copy, to be pasted
+    fn take_system_clipboard(&mut self) {
+        match clipboard::paste() {
+            Ok(Some(text)) => {
+                let known = self
+                    .yank_ring
+                    .get(0)
+                    .map_or(false, |yank| yank.text == text);
+                if !text.is_empty() && !known {
+                    self.yank_ring.push(text, false);
+                }
+            }
+            Ok(None) => (),
+            Err(error) => self.status_message = StatusMessage::error(error.to_string()),
+        }
+    }
+
+    /// Insert the text as whole lines below the current one, whatever the cursor column
+    fn paste_lines(&mut self, text: &str) {
+        self.clear_selection();
+        let lines = text.strip_suffix('\n').unwrap_or(text);
+        let y = self.cursor_position.y;
+        let (start, text) = if self.document.is_empty() {
+            (Position::default(), lines.to_string())
+        } else if y + 1 < self.document.len() {
+            (Position { x: 0, y: y + 1 }, format!("{}\n", lines))
+        } else {
+            // below the last line: end it first
+            let y = self.last_line();
+            let x = self.document.row(y).map_or(0, |row| row.len());
+            (Position { x, y }, format!("\n{}", lines))
+        };
+        let end = self.document.insert_text(&text, &start);
+        self.cursor_position = end.clone();
+        self.last_paste = Some(Paste { start, end, age: 0 });
+    }
+
+    /// Paste the latest copied text as lines below the current one, even if copied mid-line
+    fn paste_as_lines(&mut self) {
+        if self.config.system_clipboard {
+            self.take_system_clipboard();
+        }
+        let text = match self.yank_ring.get(0) {
+            Some(yank) => yank.text.clone(),
+            None => {
+                self.status_message = StatusMessage::info("Nothing to paste".to_string());
+                return;
+            }
+        };
+        self.paste_lines(&text);
+    }
+
+    /// Right after a paste, replace the pasted text with the previous entry of the yank ring
+    fn yank_pop(&mut self) {
+        let paste = match self.last_paste.take() {
+            Some(paste) => paste,
+            None => {
+                self.status_message =
+                    StatusMessage::info("Yank-pop only works right after a paste".to_string());
+                return;
+            }
+        };
+        let age = paste.age + 1;
+        let text = match self.yank_ring.get(age) {
+            Some(yank) => yank.text.clone(),
+            None => return,
+        };
+        self.document.delete_range(&paste.start, &paste.end);
+        let end = self.document.insert_text(&text, &paste.start);
+        self.cursor_position = end.clone();
+        self.last_paste = Some(Paste {
+            start: paste.start,
+            end,
+            age,
+        });
+    }
+
+    /// Insert a typed character, or replace the one under the cursor in overwrite mode
+    fn type_char(&mut self, c: char) {
+        if self.delete_selection() || !self.overwrite {
+            self.insert_char(c);
+        } else {
+            self.document.replace_char(c, &self.cursor_position);
+            self.move_cursor(KeyEvent::Right)
+        }
+    }
+
+    ///
+    /// Type a character, closing the pairs configured for the filetype:
+    /// an opener also inserts its closer, typing a closer in front of the same one steps over it.
+    /// Nothing is closed inside strings and comments, nor a quote following a word (`it's`).
+    ///
+    fn insert_char(&mut self, c: char) {
+        let Position { x, y } = self.cursor_position;
+        let pairs = self
+            .config
+            .auto_close_pairs_for(&self.document.filetype.name())
+            .to_vec();
+        let closers: Vec<char> = pairs
+            .iter()
+            .filter_map(|&opener| closing_pair(opener))
+            .collect();
+        let (previous, next, previous_type) = match self.document.row(y) {
+            Some(row) => {
+                let mut chars = row.text().chars().skip(x.saturating_sub(1));
+                let previous = if x > 0 { chars.next() } else { None };
+                let previous_type = x
+                    .checked_sub(1)
+                    .and_then(|index| row.highlighting().get(index).cloned());
+                (previous, chars.next(), previous_type)
+            }
+            None => (None, None, None),
+        };
+        if closers.contains(&c) && next == Some(c) {
+            self.move_cursor(KeyEvent::Right);
+            return;
+        }
+        self.document.insert(c, &self.cursor_position);
+        self.move_cursor(KeyEvent::Right);
+
+        let closer = match closing_pair(c) {
+            Some(closer) if pairs.contains(&c) => closer,
+            _ => return,
+        };
+        let in_literal = match previous_type {
+            Some(highlighting::Type::String)
+            | Some(highlighting::Type::Character)
+            | Some(highlighting::Type::Comment)
+            | Some(highlighting::Type::MultilineComment) => true,
+            _ => false,
+        };
+        let apostrophe = c == closer && previous.map_or(false, |c| c.is_alphanumeric());
+        if !in_literal && !apostrophe {
+            self.document.insert(closer, &self.cursor_position);
+        }
+    }
+
+    ///
+    /// Tab depending on where the cursor is: indent the selected lines, indent the line
+    /// (cursor to the start of its text) from within the leading whitespace, else insert the indentation.
+    ///
+    fn smart_tab(&mut self) {
+        let indent = self.document.indent_unit();
+        let indent_len = indent.chars().count();
+        if let Some(selection) = self.selection.clone() {
+            let (start, end) = selection.range();
+            // a selection ending at the start of a line doesn't take that line
+            let last = if end.x == 0 && end.y > start.y {
+                end.y - 1
+            } else {
+                end.y
+            };
+            for y in start.y..=last {
+                self.document.insert_text(&indent, &Position { x: 0, y });
+            }
+            let shift = |position: &Position| Position {
+                x: if position.y <= last {
+                    position.x + indent_len
+                } else {
+                    position.x
+                },
+                y: position.y,
+            };
+            let moved = Selection {
+                anchor: shift(&selection.anchor),
+                head: shift(&selection.head),
+            };
+            self.cursor_position = moved.head.clone();
+            self.selection = Some(moved);
+            self.shade_selection(Some(selection));
+            return;
+        }
+        let Position { x, y } = self.cursor_position;
+        let (in_leading_whitespace, text_start) = match self.document.row(y) {
+            Some(row) => {
+                let leading = row.text().chars().take_while(|c| c.is_whitespace()).count();
+                (x <= leading, leading)
+            }
+            None => (true, 0),
+        };
+        if in_leading_whitespace {
+            self.document.insert_text(&indent, &Position { x: 0, y });
+            self.cursor_position.x = text_start + indent_len;
+        } else {
+            self.document.insert_text(&indent, &self.cursor_position);
+            self.cursor_position.x += indent_len;
+        }
+    }
+
+    ///
+    /// How many spaces Backspace removes to reach the previous indent level,
+    /// 1 unless the cursor is in indentation made of spaces
+    ///
+    fn indent_to_remove(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        let in_spaces = self
+            .document
+            .row(y)
+            .map_or(false, |row| row.text().chars().take(x).all(|c| c == ' '));
+        if x == 0 || !in_spaces {
+            return 1;
+        }
+        let indent = self.document.indent_unit();
+        let level = if indent.starts_with(' ') {
+            indent.len()
+        } else {
+            self.document.tab_width()
+        };
+        (x - 1) % level + 1
+    }
+
+    /// Whether the cursor sits between an opener and its closer, e.g. `(|)`
+    fn in_empty_pair(&self) -> bool {
+        let Position { x, y } = self.cursor_position;
+        if x == 0 {
+            return false;
+        }
+        let pairs = self
+            .config
+            .auto_close_pairs_for(&self.document.filetype.name());
+        let mut chars = match self.document.row(y) {
+            Some(row) => row.text().chars().skip(x - 1),
+            None => return false,
+        };
+        match (chars.next(), chars.next()) {
+            (Some(opener), Some(closer)) => {
+                pairs.contains(&opener) && closing_pair(opener) == Some(closer)
+            }
+            _ => false,
+        }
+    }
+
+    /// Wrap the selection with `opener` and its matching closer, keeping the original text selected
+    fn surround_selection(&mut self, opener: char) {
+        let (selection, closer) = match (self.selection.take(), closing_pair(opener)) {
+            (Some(selection), Some(closer)) => (selection, closer),
+            _ => return,
+        };
+        let (start, end) = selection.range();
+        // closer first so the start position stays valid
+        self.document.insert_text(&closer.to_string(), &end);
+        self.document.insert_text(&opener.to_string(), &start);
+        let new_start = Position {
+            x: start.x + 1,
+            y: start.y,
+        };
+        let new_end = Position {
+            x: if end.y == start.y { end.x + 1 } else { end.x },
+            y: end.y,
+        };
+        let (anchor, head) = if selection.anchor == start {
+            (new_start, new_end)
+        } else {
+            (new_end, new_start)
+        };
+        self.cursor_position = head.clone();
+        self.selection = Some(Selection { anchor, head });
+        self.shade_selection(Some(selection));
+    }
+
+    ///
+    /// Wrap the word (letters, digits and underscores) under the cursor with `opener` and its
+    /// matching closer, leaving the cursor after the closer
+    ///
+    fn surround_word(&mut self, opener: char) {
+        let closer = match closing_pair(opener) {
+            Some(closer) => closer,
+            None => return,
+        };
+        let Position { x, y } = self.cursor_position;
+        let chars: Vec<char> = match self.document.row(y) {
+            Some(row) => row.text().chars().collect(),
+            None => return,
+        };
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let x = x.min(chars.len());
+        let start = x - chars[..x].iter().rev().take_while(|c| is_word(c)).count();
+        let end = x + chars[x..].iter().take_while(|c| is_word(c)).count();
+        if start == end {
+            self.status_message = StatusMessage::info("No word under the cursor".to_string());
+            return;
+        }
+        self.document
+            .insert_text(&closer.to_string(), &Position { x: end, y });
+        self.document
+            .insert_text(&opener.to_string(), &Position { x: start, y });
+        self.cursor_position = Position { x: end + 2, y };
+        self.status_message = StatusMessage::default();
+    }
+
+    /// Insert a copy of the selection (or of the current line) right after it
+    fn duplicate_selection(&mut self) {
+        match self.selection.clone() {
+            Some(selection) => {
+                let (start, end) = selection.range();
+                let text = self.document.text_range(&start, &end);
+                self.document.insert_text(&text, &end);
+                self.shade_selection(Some(selection));
+            }
+            None => {
+                let y = self.cursor_position.y;
+                if let Some(row) = self.document.row(y) {
+                    let line = format!("\n{}", row.text());
+                    let end_of_line = Position { x: row.len(), y };
+                    self.document.insert_text(&line, &end_of_line);
+                }
+            }
+        }
+    }
+
+    ///
+    /// Put the selected lines (or the current one) between an opening and a closing marker line:
+    /// a code fence in Markdown, a block comment in other languages.
+    ///
+    fn wrap_lines(&mut self) {
+        if self.document.is_empty() {
+            return;
+        }
+        let (open, close) = if self.document.filetype.is_markdown() {
+            let language = match self.prompt("Fence language: ") {
+                Ok(language) => language,
+                Err(_) => return,
+            };
+            (format!("```{}", language.trim()), "```".to_string())
+        } else {
+            match self.document.filetype.block_comment() {
+                Some((open, close)) => (open.to_string(), close.to_string()),
+                None => {
+                    self.status_message = StatusMessage::info(format!(
+                        "No block comments for {}",
+                        self.document.filetype
+                    ));
+                    return;
+                }
+            }
+        };
+        let (first, last) = self.selected_lines();
+        let indent: String = self.document.row(first).map_or(String::new(), |row| {
+            row.text()
+                .chars()
+                .take_while(|c| c.is_whitespace())
+                .collect()
+        });
+        self.document.insert_text(
+            &format!("{}{}\n", indent, open),
+            &Position { x: 0, y: first },
+        );
+        // everything moved one line down
+        let last = last + 1;
+        let end_of_last = Position {
+            x: self.document.row(last).map_or(0, |row| row.len()),
+            y: last,
+        };
+        self.document
+            .insert_text(&format!("\n{}{}", indent, close), &end_of_last);
+        if self.reselect_lines("wrap", first, last + 1) {
+            return;
+        }
+
+        let previous = self.selection.clone();
+        if let Some(selection) = &mut self.selection {
+            selection.anchor.y += 1;
+            selection.head.y += 1;
+        }
+        self.cursor_position.y += 1;
+        self.shade_selection(previous);
+    }
+
+    /// Comment out the selected lines (or the current one), or uncomment them
+    fn toggle_comment(&mut self) {
+        let (first, last) = self.selected_lines();
+        if self.document.toggle_comment(first, last).is_none() {
+            self.status_message =
+                StatusMessage::info(format!("No comments for {}", self.document.filetype));
+            return;
+        }
+        if self.reselect_lines("comment", first, last) {
+            return;
+        }
+        if self.selection.is_some() {
+            self.clamp_selection();
+        } else {
+            let position = self.cursor_position.clone();
+            self.jump_to(position);
+        }
+    }
+
+    /// The rows of the selected lines, or the current row when nothing is selected
+    fn selected_lines(&self) -> (usize, usize) {
+        match &self.selection {
+            Some(selection) => {
+                let (start, end) = selection.range();
+                // a selection ending at the start of a line does not include that line
+                let last = if end.x == 0 && end.y > start.y {
+                    end.y - 1
+                } else {
+                    end.y
+                };
+                (start.y, last)
+            }
+            None => (self.cursor_position.y, self.cursor_position.y),
+        }
+    }
+
+    ///
+    /// After `command` transformed the selected lines, now `first..=last`, select them whole
+    /// when the configuration asks for it. Returns false when the command should place
+    /// the cursor itself: not configured, or there was no selection to begin with.
+    ///
+    fn reselect_lines(&mut self, command: &str, first: usize, last: usize) -> bool {
+        if self.selection.is_none() || !self.config.reselect_after.iter().any(|c| c == command) {
+            return false;
+        }
+        let head = if last + 1 < self.document.len() {
+            Position { x: 0, y: last + 1 }
+        } else {
+            Position {
+                x: self.document.row(last).map_or(0, |row| row.len()),
+                y: last,
+            }
+        };
+        let previous = self.selection.replace(Selection {
+            anchor: Position { x: 0, y: first },
+            head: head.clone(),
+        });
+        self.cursor_position = head;
+        self.shade_selection(previous);
+        true
+    }
+
+    /// Redraw the selection shading, clearing whatever the previous selection covered
+    fn shade_selection(&mut self, previous: Option<Selection>) {
+        let mut rows: Vec<usize> = Vec::new();
+        for selection in previous
+            .iter()
+            .chain(self.selection.iter())
+            .chain(self.other_cursors.iter())
+        {
+            rows.push(selection.anchor.y);
+            rows.push(selection.head.y);
+        }
+        if let (Some(first), Some(last)) = (rows.iter().min(), rows.iter().max()) {
+            self.document.highlight_rows(*first, *last);
+        }
+        if let Some(selection) = &self.selection {
+            let (start, end) = selection.range();
+            self.document
+                .shade(&start, &end, highlighting::Type::Selection);
+        }
+        // bare cursors show as a shaded cell
+        for selection in &self.other_cursors {
+            let (start, mut end) = selection.range();
+            if start == end {
+                end.x += 1;
+            }
+            self.document
+                .shade(&start, &end, highlighting::Type::Selection);
+        }
+    }
+
+    ///
+    /// Shade the bracket under the cursor and the one matching it, or the bracket alone when
+    /// nothing matches it. The pair shaded before is cleared when the cursor moved away.
+    ///
+    fn shade_brackets(&mut self) {
+        let mut shading = Vec::new();
+        if self.config.match_brackets {
+            let cursor = self.cursor_position.clone();
+            match self.document.matching_bracket(&cursor) {
+                Some(Some(other)) => {
+                    shading.push((cursor, highlighting::Type::Match));
+                    shading.push((other, highlighting::Type::Match));
+                }
+                Some(None) => shading.push((cursor, highlighting::Type::UnmatchedBracket)),
+                None => (),
+            }
+        }
+        let positions: Vec<Position> = shading.iter().map(|(at, _)| at.clone()).collect();
+        if positions != self.shaded_brackets {
+            let stale: Vec<usize> = self.shaded_brackets.iter().map(|at| at.y).collect();
+            for y in stale {
+                self.document.highlight_rows(y, y);
+            }
+            // highlighting drops the selection shading too
+            self.shade_selection(None);
+            self.shaded_brackets = positions;
+        }
+        // edits and selections shade rows again, the pair is shaded on every redraw
+        for (at, shading_type) in shading {
+            let end = Position {
+                x: at.x + 1,
+                y: at.y,
+            };
+            self.document.shade(&at, &end, shading_type);
+        }
+    }
+
+    /// The screen column of the cursor in its row, before horizontal scrolling
+    fn cursor_render_x(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        self.document.row(y).map_or(x, |row| {
+            row.render_x(x, self.show_control, self.document.tab_width())
+        })
+    }
+
+    /// Scroll so the cursor's line is at the top, center or bottom of the screen, the cursor staying put
+    fn place_cursor_line(&mut self, placement: LinePlacement) {
+        let y = self.cursor_position.y;
+        let height = self.terminal.height().max(1);
+        self.offset.y = match placement {
+            LinePlacement::Top => y,
+            LinePlacement::Center => y.saturating_sub(height / 2),
+            LinePlacement::Bottom => (y + 1).saturating_sub(height),
+        };
+    }
+
+    fn scroll(&mut self) {
+        let y = self.cursor_position.y;
+        let x = self.cursor_render_x();
+        // keep the cursor's own cell in view even on a transiently zero-sized terminal
+        let width = self.text_width().max(1);
+        let height = self.terminal.height().max(1);
         let mut offset = &mut self.offset;
 
         if y < offset.y {
@@ -283,14 +2179,13 @@ impl Editor {
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{}{}", spaces, welcome_message);
         welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        print!("{}\r", welcome_message);
     }
-    fn draw_status_bar(&self) {
-        let width = self.terminal.width();
-        let default_filename = "[No name]".to_string();
+    /// The two halves of the status bar: file information on the left, position on the right
+    fn status_parts(&self) -> (String, String) {
         let filename = match &self.document.filename {
-            Some(filename) => filename,
-            None => &default_filename,
+            Some(filename) => self.display_name(filename),
+            None => "[No name]".to_string(),
         };
 
         let is_modified = if self.document.is_dirty() {
@@ -304,47 +2199,258 @@ impl Editor {
             self.document.len(),
             is_modified
         );
+        if !self.buffers.is_empty() {
+            status = format!(
+                "[{}/{}] {}",
+                self.active_buffer + 1,
+                self.buffers.len() + 1,
+                status
+            );
+        }
+        if let Some(count) = self.pending_count {
+            status = format!("{} {}", count, status);
+        }
+        let char_info = if self.show_char_info {
+            format!("{} | ", self.char_info())
+        } else {
+            String::new()
+        };
+        let selection_size = match self.selection_size() {
+            Some(size) => format!("{} ", size),
+            None => String::new(),
+        };
+        let mode = if self.document.is_read_only() {
+            "[RO]"
+        } else if self.overwrite {
+            "[OVR]"
+        } else {
+            "[INS]"
+        };
+        // the column as seen on screen, tabs expanded (the byte offset is in the char info)
         let line_indicator = format!(
-            "{} | {}/{}",
+            "{}{}{} {} {} | {}/{} col {} {}",
+            selection_size,
+            char_info,
+            mode,
             self.document.filetype,
+            self.document.end_of_line(),
             self.cursor_position.y + 1,
-            self.document.len()
+            self.document.len(),
+            self.cursor_render_x() + 1,
+            self.position_in_file()
         );
+        (status, line_indicator)
+    }
 
-        let length = status.len() + line_indicator.len();
-        status.push_str(&" ".repeat(width.saturating_sub(length)));
-        status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
-        // self.terminal.cursor_position(&Position{x: 0, y: height});
-        if self.document.is_dirty() {
-            self.terminal.set_bg_color(Color::Red);
+    ///
+    /// The size of the selection in characters (line breaks included), and in lines when it
+    /// spans several, e.g. `[12 chars, 3 lines]`
+    ///
+    fn selection_size(&self) -> Option<String> {
+        let (start, end) = self.selection.as_ref()?.range();
+        let chars: usize = (start.y..=end.y)
+            .filter_map(|y| self.document.row(y).map(|row| (y, row.len())))
+            .map(|(y, len)| {
+                let from = if y == start.y { start.x.min(len) } else { 0 };
+                let to = if y == end.y { end.x.min(len) } else { len + 1 };
+                to - from
+            })
+            .sum();
+        // a selection ending at the start of a line doesn't take any of it
+        let lines = if end.x == 0 {
+            end.y - start.y
+        } else {
+            end.y - start.y + 1
+        };
+        if lines <= 1 {
+            Some(format!("[{} chars]", chars))
         } else {
-            self.terminal.set_bg_color(Color::DarkCyan);
+            Some(format!("[{} chars, {} lines]", chars, lines))
         }
-        println!("{}", status);
-        self.terminal.reset_bg_color();
-        // self.terminal.cursor_position(&self.cursor_position);
     }
 
-    fn draw_message_bar(&self) {
-        self.terminal.clear_current_line();
-        let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.width());
-            print!("{}", text);
+    /// How many rows the status bar takes, two when allowed and one isn't enough
+    fn status_lines(&self) -> usize {
+        if !self.config.two_line_status {
+            return 1;
+        }
+        let (status, line_indicator) = self.status_parts();
+        // filenames may be multibyte, measure on characters rather than bytes
+        if status.chars().count() + line_indicator.chars().count() < self.terminal.width() {
+            1
+        } else {
+            2
         }
     }
-    fn prompt(&mut self, message: &str) -> Result<String, std::io::Error> {
-        let mut result = String::new();
-        loop {
-            self.status_message = StatusMessage::from(format!("{}{}", message, result));
-            self.refresh_screen()?;
+
+    /// Give the bars the rows they need before drawing, the text area taking the rest
+    fn layout_bars(&mut self) {
+        let rows = self.status_lines() + 1;
+        if rows != self.terminal.bar_rows() {
+            self.terminal.set_bar_rows(rows);
+            self.scroll();
+        }
+    }
+
+    fn draw_status_bar(&self) {
+        let width = self.terminal.width();
+        let (status, line_indicator) = self.status_parts();
+        let lines = if self.status_lines() == 1 {
+            let length = status.chars().count() + line_indicator.chars().count();
+            vec![format!(
+                "{}{}{}",
+                status,
+                " ".repeat(width.saturating_sub(length)),
+                line_indicator
+            )]
+        } else {
+            // the file on the first row, the position right aligned on the second
+            let length = line_indicator.chars().count();
+            vec![
+                status,
+                format!(
+                    "{}{}",
+                    " ".repeat(width.saturating_sub(length)),
+                    line_indicator
+                ),
+            ]
+        };
+        let color = if !self.config.status_bar_colors {
+            None
+        } else if self.document.is_dirty() {
+            Some(Color::Red)
+        } else {
+            Some(Color::DarkCyan)
+        };
+        let (status_row, _) = self.bar_rows();
+        for (i, line) in lines.iter().enumerate() {
+            self.terminal.cursor_position(&Position {
+                x: 0,
+                y: status_row + i,
+            });
+            // the background fills the whole row
+            self.terminal.print_bar(line, width, color);
+        }
+    }
+
+    /// The file name as shown to the user, relative to the project root when asked for
+    fn display_name(&self, filename: &str) -> String {
+        let root = match &self.project_root {
+            Some(root) if self.config.relative_paths => root,
+            _ => return filename.to_string(),
+        };
+        let path = std::fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+        match path.strip_prefix(root) {
+            Ok(relative) => relative.to_string_lossy().to_string(),
+            Err(_) => filename.to_string(),
+        }
+    }
+
+    /// Byte offset of the cursor and the character under it, e.g. `@12 U+00E9 0xC3 0xA9`
+    fn char_info(&self) -> String {
+        let offset = self.document.byte_offset(&self.cursor_position);
+        let character = self
+            .document
+            .row(self.cursor_position.y)
+            .and_then(|row| row.text().chars().nth(self.cursor_position.x));
+        match character {
+            Some(c) => {
+                let mut bytes = [0; 4];
+                let hex: Vec<String> = c
+                    .encode_utf8(&mut bytes)
+                    .bytes()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect();
+                format!("@{} U+{:04X} {}", offset, c as u32, hex.join(" "))
+            }
+            None => format!("@{} EOL", offset),
+        }
+    }
+
+    /// How far through the file the cursor is, Vim style: `All`, `Top`, `Bot` or a percentage
+    fn position_in_file(&self) -> String {
+        let len = self.document.len();
+        let y = self.cursor_position.y;
+        if len <= 1 {
+            String::from("All")
+        } else if y == 0 {
+            String::from("Top")
+        } else if y + 1 >= len {
+            String::from("Bot")
+        } else {
+            format!("{}%", (y + 1) * 100 / len)
+        }
+    }
+
+    fn draw_message_bar(&self) {
+        let (_, message_row) = self.bar_rows();
+        self.terminal.cursor_position(&Position {
+            x: 0,
+            y: message_row,
+        });
+        self.terminal.clear_current_line();
+        let message = &self.status_message;
+        let timeout = message
+            .timeout
+            .unwrap_or(self.config.status_message_timeout);
+        let text = ellipsize(&message.text, self.terminal.width());
+        if message.severity == Severity::Error {
+            self.terminal.set_fg_color(Color::Red);
+            print!("{}", text);
+            self.terminal.reset_fg_color();
+        } else if Instant::now() - message.time < timeout {
+            print!("{}", text);
+        }
+    }
+
+    ///
+    /// Show an error too long for the message bar in full, wrapped over the text rows next to it
+    /// (up to half the screen). Like the message itself it goes away with the next key.
+    ///
+    fn draw_expanded_message(&self) {
+        let message = &self.status_message;
+        let width = self.terminal.width();
+        if message.severity != Severity::Error || ellipsize(&message.text, width) == message.text {
+            return;
+        }
+        let mut lines = wrap_message(&message.text, width);
+        let height = self.terminal.height();
+        let max_lines = (height / 2).max(1);
+        if lines.len() > max_lines {
+            lines.truncate(max_lines);
+            if let Some(last) = lines.last_mut() {
+                *last = ellipsize(&format!("{}…", last), width);
+            }
+        }
+        let top = if self.config.status_bar_top {
+            self.text_top()
+        } else {
+            self.text_top() + height.saturating_sub(lines.len())
+        };
+        self.terminal.set_fg_color(Color::Red);
+        for (i, line) in lines.iter().enumerate() {
+            self.terminal
+                .cursor_position(&Position { x: 0, y: top + i });
+            self.terminal.clear_current_line();
+            print!("{}", line);
+        }
+        self.terminal.reset_fg_color();
+    }
+    fn prompt(&mut self, message: &str) -> Result<String, std::io::Error> {
+        Ok(self.prompt_or_cancel(message)?.unwrap_or_default())
+    }
+
+    /// Like `prompt`, telling an empty answer (`Some("")`) from Esc (`None`)
+    fn prompt_or_cancel(&mut self, message: &str) -> Result<Option<String>, std::io::Error> {
+        let mut result = String::new();
+        loop {
+            self.status_message = StatusMessage::info(format!("{}{}", message, result));
+            self.refresh_screen()?;
             if let Some(event) = self.terminal.read_keypress() {
                 match event {
                     InputEvent::Keyboard(key) => match key {
                         KeyEvent::Enter => {
-                            self.status_message = StatusMessage::from(String::new());
+                            self.status_message = StatusMessage::info(String::new());
                             break;
                         }
                         KeyEvent::Char(c) => {
@@ -356,9 +2462,8 @@ impl Editor {
                             }
                         }
                         KeyEvent::Esc => {
-                            self.status_message = StatusMessage::from(String::new());
-                            result.clear();
-                            break;
+                            self.status_message = StatusMessage::info(String::new());
+                            return Ok(None);
                         }
                         _ => (),
                     },
@@ -366,16 +2471,265 @@ impl Editor {
                 }
             };
         }
-        Ok(result)
+        Ok(Some(result))
+    }
+
+    /// Save the active buffer without prompting, unnamed or externally changed files are skipped
+    fn auto_save(&mut self) {
+        if !self.document.is_dirty() || self.document.filename.is_none() {
+            return;
+        }
+        if self.document.changed_on_disk() {
+            self.status_message =
+                StatusMessage::info("Not auto saved, file changed on disk".to_string());
+            return;
+        }
+        self.status_message = match self.document.save(self.config.sync_on_save) {
+            Ok(written) => StatusMessage::info(format!("Auto saved, {} bytes written", written)),
+            Err(error) => StatusMessage::error(format!("Auto save failed: {}", error)),
+        };
+    }
+
+    /// Make the buffer at `index` (counting the active one) the active buffer
+    fn switch_buffer(&mut self, index: usize) {
+        if index > self.buffers.len() || index == self.active_buffer {
+            return;
+        }
+        self.clear_selection();
+        // crossterm doesn't report terminal focus changes, switching buffers is our only cue
+        if self.config.auto_save_on_switch {
+            self.auto_save();
+        }
+        // park the active document at its place, then take the requested one out
+        let current = Buffer {
+            document: std::mem::take(&mut self.document),
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone(),
+        };
+        self.buffers.insert(self.active_buffer, current);
+        let next = self.buffers.remove(index);
+        self.load_buffer(next);
+        self.active_buffer = index;
+    }
+
+    fn load_buffer(&mut self, buffer: Buffer) {
+        self.document = buffer.document;
+        self.cursor_position = buffer.cursor_position;
+        self.offset = buffer.offset;
+        self.jump_list.clear();
+        self.follow = false;
+    }
+
+    fn toggle_follow(&mut self) {
+        if self.document.filename.is_none() {
+            self.status_message = StatusMessage::info("No file to follow".to_string());
+            return;
+        }
+        self.follow = !self.follow;
+        if self.follow {
+            self.follow_end();
+            self.status_message = StatusMessage::info("Following file changes".to_string());
+        } else {
+            self.status_message = StatusMessage::info("Stopped following".to_string());
+        }
+    }
+
+    fn follow_end(&mut self) {
+        self.clear_selection();
+        let y = self.last_line();
+        let x = self.document.row(y).map_or(0, |row| row.len());
+        self.cursor_position = Position { x, y };
+        self.scroll();
+    }
+
+    /// Reload the active file if it changed on disk, never when it has unsaved changes
+    fn reload_changed_file(&mut self) {
+        if !(self.config.auto_reload || self.follow)
+            || self.document.is_dirty()
+            || !self.document.modified_on_disk()
+        {
+            return;
+        }
+        self.clear_selection();
+        if let Err(error) = self.document.reload() {
+            self.status_message = StatusMessage::error(error.to_string());
+            return;
+        }
+        if self.follow {
+            self.follow_end();
+        } else {
+            let position = self.cursor_position.clone();
+            self.jump_to(position);
+            self.status_message = StatusMessage::info("Reloaded, file changed on disk".to_string());
+        }
+    }
+
+    /// Open again the most recently closed buffer whose file still exists, after the active one
+    fn reopen_closed_buffer(&mut self) {
+        let (filename, position) = loop {
+            match self.closed_buffers.pop() {
+                Some((filename, _)) if !Path::new(&filename).exists() => continue,
+                Some(closed) => break closed,
+                None => {
+                    self.status_message =
+                        StatusMessage::info("No closed buffer to reopen".to_string());
+                    return;
+                }
+            }
+        };
+        let document = match self.open_document(&filename) {
+            Ok(document) => document,
+            Err(error) => {
+                self.status_message = StatusMessage::error(error.to_string());
+                return;
+            }
+        };
+        self.clear_selection();
+        // an untouched empty buffer is simply replaced
+        let replace = self.document.filename.is_none()
+            && self.document.is_empty()
+            && !self.document.is_dirty();
+        if !replace {
+            let current = Buffer {
+                document: std::mem::take(&mut self.document),
+                cursor_position: self.cursor_position.clone(),
+                offset: self.offset.clone(),
+            };
+            self.buffers.insert(self.active_buffer, current);
+            self.active_buffer += 1;
+        }
+        self.load_buffer(Buffer {
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+        });
+        self.jump_to(position);
+        self.status_message = StatusMessage::info(format!("Reopened {}", filename));
+    }
+
+    /// Close the active buffer and focus its neighbor, the last buffer is replaced by an empty one
+    fn close_buffer(&mut self) {
+        if self.document.is_dirty() {
+            match self.prompt("Close without saving? (Y/N)") {
+                Ok(answer) if answer.to_lowercase() == "y" => (),
+                _ => return,
+            }
+        }
+        self.selection = None;
+        if let Some(filename) = self.document.filename.clone() {
+            self.closed_buffers
+                .push((filename, self.cursor_position.clone()));
+            if self.closed_buffers.len() > MAX_CLOSED_BUFFERS {
+                self.closed_buffers.remove(0);
+            }
+        }
+        if self.buffers.is_empty() {
+            self.load_buffer(Buffer {
+                document: new_document(&self.config, &self.options),
+                cursor_position: Position::default(),
+                offset: Position::default(),
+            });
+        } else {
+            // the buffer after the closed one takes its place, unless it was the last one
+            let index = self.active_buffer.min(self.buffers.len() - 1);
+            let next = self.buffers.remove(index);
+            self.load_buffer(next);
+            self.active_buffer = index;
+        }
+        self.status_message = StatusMessage::info("Buffer closed".to_string());
+    }
+
+    /// Read a command at the prompt and run it
+    fn command_prompt(&mut self) -> Result<(), std::io::Error> {
+        let text = match self.prompt_or_cancel(":")? {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        match parse_command(&text) {
+            Some(command) => self.run_command(command),
+            None => {
+                self.status_message =
+                    StatusMessage::error(format!("Unknown command: {}", text.trim()));
+                Ok(())
+            }
+        }
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<(), std::io::Error> {
+        match command {
+            Command::Execute(action) => action(self),
+            Command::Save => {
+                self.save()?;
+            }
+            Command::Search(None) => self.search(),
+            Command::Search(Some(query)) => {
+                if let Some(pattern) = self.search_pattern(&query) {
+                    match self.document.find(
+                        &pattern,
+                        self.cursor_position.clone(),
+                        SearchDirection::Forward,
+                    ) {
+                        Some((_, end)) => {
+                            self.jump_list.push(self.cursor_position.clone());
+                            self.jump_to(end);
+                        }
+                        None => {
+                            self.status_message =
+                                StatusMessage::info(format!("No more matches for '{}'", query));
+                        }
+                    }
+                }
+            }
+            Command::Cancel => self.status_message = StatusMessage::default(),
+            Command::Quit => {
+                self.quit()?;
+            }
+            Command::ForceQuit => self.should_quit = true,
+            Command::SaveAndQuit => {
+                self.save()?;
+                // a failed or cancelled save leaves the buffer dirty, and us in the editor
+                if !self.document.is_dirty() {
+                    self.quit()?;
+                }
+            }
+        }
+        Ok(())
     }
 
     fn quit(&mut self) -> Result<String, std::io::Error> {
-        if !self.document.is_dirty() {
-            self.should_quit = true;
+        let any_dirty = self.document.is_dirty()
+            || self.buffers.iter().any(|buffer| buffer.document.is_dirty());
+        // a single empty unmodified buffer has nothing to lose, leave right away
+        let single_empty = self.buffers.is_empty() && self.document.is_empty() && !any_dirty;
+        if any_dirty {
+            self.quit_times = self.quit_times.saturating_sub(1);
+            if self.quit_times > 0 {
+                self.status_message = StatusMessage::error(format!(
+                    "File has unsaved changes. Press Ctrl-Q {} more {} to quit",
+                    self.quit_times,
+                    if self.quit_times == 1 {
+                        "time"
+                    } else {
+                        "times"
+                    }
+                ));
+            } else {
+                self.should_quit = true;
+            }
+            return Ok(String::new());
+        }
+        let question = if self.config.confirm_quit_always && !single_empty {
+            Some("Quit? (Y/N)")
         } else {
-            if let Ok(answer) = self.prompt("Quit without saving? (Y/N)") {
-                if answer.to_lowercase() == "y" {
-                    self.should_quit = true;
+            None
+        };
+        match question {
+            None => self.should_quit = true,
+            Some(question) => {
+                if let Ok(answer) = self.prompt(question) {
+                    if answer.to_lowercase() == "y" {
+                        self.should_quit = true;
+                    }
                 }
             }
         }
@@ -383,6 +2737,11 @@ impl Editor {
     }
 
     fn save(&mut self) -> Result<String, std::io::Error> {
+        // reformatting before the save would otherwise edit the buffer
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::info("The buffer is read-only".to_string());
+            return Ok(String::from("The buffer is read-only"));
+        }
         if self.document.filename.is_none() {
             if let Some(document) = Some(self.prompt("Save as:")?) {
                 if !document.is_empty() {
@@ -391,16 +2750,518 @@ impl Editor {
             }
         }
         if self.document.filename.is_some() {
-            self.document.save()?;
+            if self.config.confirm_external_overwrite
+                && self.document.is_dirty()
+                && self.document.changed_on_disk()
+            {
+                let answer = self.prompt("File changed on disk, overwrite it? (Y/N)")?;
+                if answer.to_lowercase() != "y" {
+                    self.status_message = StatusMessage::info("Save cancelled".to_string());
+                    return Ok(String::from("Save cancelled"));
+                }
+            }
+            let reformatted = self.hard_wrap_before_save();
+            let formatted = self.format_before_save();
+            let was_dirty = self.document.is_dirty();
+            self.status_message = match self.document.save(self.config.sync_on_save) {
+                Ok(written) if reformatted > 0 => StatusMessage::info(format!(
+                    "{} bytes written, {} lines reformatted",
+                    written, reformatted
+                )),
+                Ok(written) if was_dirty => {
+                    StatusMessage::info(format!("{} bytes written", written))
+                }
+                Ok(_) => StatusMessage::info("No changes to save".to_string()),
+                Err(error) => StatusMessage::error(error.to_string()),
+            };
+            if let (Err(error), Severity::Info) = (formatted, &self.status_message.severity) {
+                self.status_message =
+                    StatusMessage::error(format!("Saved without formatting, {}", error));
+            }
         }
         return Ok(String::from("Saved successfully..."));
     }
 
+    /// Pipe a modified document through the formatter configured for its filetype, if any
+    fn format_before_save(&mut self) -> Result<(), EditorError> {
+        let command = match self
+            .config
+            .format_commands
+            .get(&self.document.filetype.name())
+        {
+            Some(command) if self.document.is_dirty() => command.clone(),
+            _ => return Ok(()),
+        };
+        if self.document.pipe_through(&command)? {
+            // lines may have moved, stay around the same place
+            self.clear_selection();
+            let position = self.cursor_position.clone();
+            self.jump_to(position);
+        }
+        Ok(())
+    }
+
+    /// Reflow the document if its filetype opted in to hard wrapping, returns the lines reformatted
+    fn hard_wrap_before_save(&mut self) -> usize {
+        let filetype = self.document.filetype.name();
+        if !self.config.hard_wrap_filetypes.contains(&filetype) {
+            return 0;
+        }
+        let reformatted = self.document.hard_wrap(self.config.hard_wrap_width);
+        if reformatted > 0 {
+            self.clear_selection();
+            let position = self.cursor_position.clone();
+            self.jump_to(position);
+        }
+        reformatted
+    }
+
+    /// Renumber the selected lines with a sequence starting from the number under the cursor column
+    fn number_column(&mut self) {
+        let (start, end) = match &self.selection {
+            Some(selection) => selection.range(),
+            None => {
+                self.status_message =
+                    StatusMessage::info("Select the lines to number first".to_string());
+                return;
+            }
+        };
+        let column = self
+            .selection
+            .as_ref()
+            .map_or(0, |selection| selection.anchor.x);
+        self.status_message = match self.document.number_column(start.y, end.y, column) {
+            Some(updated) => StatusMessage::info(format!("{} lines updated", updated)),
+            None => StatusMessage::info("No number under the cursor".to_string()),
+        };
+        let (first, last) = self.selected_lines();
+        if !self.reselect_lines("number", first, last) {
+            self.clamp_selection();
+        }
+    }
+
+    /// Pad the selected lines so the delimiter asked for lines up on all of them
+    fn align_selection(&mut self) {
+        if self.selection.is_none() {
+            self.status_message =
+                StatusMessage::info("Select the lines to align first".to_string());
+            return;
+        }
+        let delimiter = match self.prompt("Align on: ") {
+            Ok(delimiter) if !delimiter.is_empty() => delimiter,
+            _ => return,
+        };
+        let (first, last) = self.selected_lines();
+        let aligned = self.document.align(first, last, &delimiter);
+        self.status_message = StatusMessage::info(format!("{} lines aligned", aligned));
+        if !self.reselect_lines("align", first, last) {
+            self.clamp_selection();
+        }
+    }
+
+    /// Ask for the line ending to save the buffer with from now on
+    fn convert_line_endings(&mut self) {
+        let answer = match self.prompt("Convert line endings to (lf, crlf, cr): ") {
+            Ok(answer) if !answer.is_empty() => answer,
+            _ => return,
+        };
+        let end_of_line = match EndOfLine::from_name(answer.trim()) {
+            Some(end_of_line) => end_of_line,
+            None => {
+                self.status_message =
+                    StatusMessage::error(format!("Unknown line ending: {}", answer));
+                return;
+            }
+        };
+        self.status_message = if self.document.set_end_of_line(end_of_line) {
+            StatusMessage::info(format!("Line endings converted to {}", end_of_line))
+        } else {
+            StatusMessage::info(format!("Line endings are {} already", end_of_line))
+        };
+    }
+
+    ///
+    /// Replace the matches of a query from the cursor on, asking at each one whether to
+    /// replace it, skip it or replace all the rest
+    ///
+    fn replace(&mut self) {
+        let prompt = if self.regex_search {
+            "Replace regex: "
+        } else {
+            "Replace: "
+        };
+        let query = match self.prompt_or_cancel(prompt) {
+            Ok(Some(query)) if !query.is_empty() => query,
+            _ => return,
+        };
+        let pattern = match self.search_pattern(&query) {
+            Some(pattern) => pattern,
+            None => return,
+        };
+        let replacement = match self.prompt_or_cancel(&format!("Replace {} with: ", query)) {
+            Ok(Some(replacement)) => replacement,
+            _ => return,
+        };
+        self.clear_selection();
+        let start_position = self.cursor_position.clone();
+        let mut at = self.cursor_position.clone();
+        let mut replaced = 0;
+        let mut replace_rest = false;
+        // matches are searched for after each replacement, so one changing the length
+        // of the line doesn't make the search skip (or repeat) the following ones
+        while let Some((start, end)) =
+            self.document
+                .find(&pattern, at.clone(), SearchDirection::Forward)
+        {
+            if !replace_rest {
+                self.cursor_position = start.clone();
+                self.scroll();
+                self.status_message = StatusMessage::info(format!(
+                    "Replace with '{}'? (y)es (n)o (a)ll, ESC to stop",
+                    replacement
+                ));
+                let _ = self.refresh_screen();
+                match self.terminal.read_keypress() {
+                    Some(InputEvent::Keyboard(KeyEvent::Char('y'))) => (),
+                    Some(InputEvent::Keyboard(KeyEvent::Char('n'))) => {
+                        at = end;
+                        continue;
+                    }
+                    Some(InputEvent::Keyboard(KeyEvent::Char('a'))) => {
+                        // the rest is replaced as a single undo step
+                        replace_rest = true;
+                        self.document.begin_batch();
+                    }
+                    Some(InputEvent::Keyboard(KeyEvent::Esc)) => break,
+                    _ => continue,
+                }
+            }
+            at = self
+                .document
+                .replace_at(&start, end.x - start.x, &replacement);
+            self.cursor_position = at.clone();
+            replaced += 1;
+        }
+        if replace_rest {
+            self.document.end_batch();
+        }
+        self.document.search_string = None;
+        self.document.hightlight();
+        self.status_message = StatusMessage::info(format!("Replaced {} occurrences", replaced));
+        if self.cursor_position != start_position {
+            self.jump_list.push(start_position);
+        }
+        let width = self
+            .document
+            .row(self.cursor_position.y)
+            .map_or(0, |row| row.len());
+        self.cursor_position.x = self.cursor_position.x.min(width);
+    }
+
+    /// Replace every match in the selection, or in the whole buffer, without asking about each
+    fn replace_all(&mut self) {
+        let query = match self.prompt("Replace all: ") {
+            Ok(query) if !query.is_empty() => query,
+            _ => return,
+        };
+        let replacement = match self.prompt_or_cancel(&format!("Replace all {} with: ", query)) {
+            Ok(Some(replacement)) => replacement,
+            _ => return,
+        };
+        let (start, end) = match &self.selection {
+            Some(selection) => selection.range(),
+            None => {
+                let y = self.document.len().saturating_sub(1);
+                let x = self.document.row(y).map_or(0, |row| row.len());
+                (Position::default(), Position { x, y })
+            }
+        };
+        let replaced = self
+            .document
+            .replace_all(&query, &replacement, &start, &end);
+        self.status_message = StatusMessage::info(format!("Replaced {} occurrences", replaced));
+        if self.selection.is_some() {
+            self.clamp_selection();
+        } else {
+            let width = self
+                .document
+                .row(self.cursor_position.y)
+                .map_or(0, |row| row.len());
+            self.cursor_position.x = self.cursor_position.x.min(width);
+        }
+    }
+
+    /// Keep the selection within its rows after their lengths changed
+    fn clamp_selection(&mut self) {
+        let previous = self.selection.clone();
+        if let Some(selection) = &mut self.selection {
+            for position in [&mut selection.anchor, &mut selection.head].iter_mut() {
+                let width = self.document.row(position.y).map_or(0, |row| row.len());
+                position.x = position.x.min(width);
+            }
+            self.cursor_position = selection.head.clone();
+        }
+        self.shade_selection(previous);
+    }
+
+    /// Show the checksum of the selection, or of the whole buffer as it would be saved
+    fn show_checksum(&mut self) {
+        let algorithm = self.config.checksum_algorithm;
+        let (what, bytes) = match &self.selection {
+            Some(selection) => {
+                let (start, end) = selection.range();
+                ("selection", self.document.text_range_bytes(&start, &end))
+            }
+            None => ("buffer", self.document.contents()),
+        };
+        self.status_message = StatusMessage::info(format!(
+            "{} of the {} ({} bytes): {}",
+            algorithm.name(),
+            what,
+            bytes.len(),
+            algorithm.hex_digest(&bytes)
+        ));
+    }
+
+    fn strip_ansi(&mut self) {
+        self.clear_selection();
+        let stripped = self.document.strip_ansi();
+        let position = self.cursor_position.clone();
+        self.jump_to(position);
+        self.status_message =
+            StatusMessage::info(format!("Stripped {} escape sequences", stripped));
+    }
+
+    /// Keep the cursor on its line (or the next one left) after removing the given rows
+    fn report_removed_lines(&mut self, removed: &[usize]) {
+        self.clear_selection();
+        let Position { x, y } = self.cursor_position;
+        let above = removed.iter().filter(|&&row| row < y).count();
+        self.jump_to(Position { x, y: y - above });
+        self.status_message = StatusMessage::info(format!("Removed {} blank lines", removed.len()));
+    }
+
+    /// Write the document as syntax highlighted HTML, next to the file unless told otherwise
+    fn export_html(&mut self) {
+        let default_path = match &self.document.filename {
+            Some(filename) => format!("{}.html", filename),
+            None => String::new(),
+        };
+        let path = match self.prompt(&format!("Export HTML as ({}): ", default_path)) {
+            Ok(path) if !path.is_empty() => path,
+            Ok(_) if !default_path.is_empty() => default_path,
+            _ => {
+                self.status_message = StatusMessage::info("Export cancelled".to_string());
+                return;
+            }
+        };
+        self.status_message = match std::fs::write(&path, export::to_html(&self.document)) {
+            Ok(()) => StatusMessage::info(format!("Exported to {}", path)),
+            Err(error) => StatusMessage::error(format!("Could not export to {}: {}", path, error)),
+        };
+    }
+
+    /// Tint the lines changed since the last save, until they are highlighted again
+    fn show_diff(&mut self) {
+        let diff = match self.document.diff_saved() {
+            Ok(diff) => diff,
+            Err(error) => {
+                self.status_message = StatusMessage::error(error.to_string());
+                return;
+            }
+        };
+        self.selection = None;
+        self.document.hightlight();
+        let (mut added, mut changed) = (0, 0);
+        for (y, change) in diff.lines.iter().enumerate() {
+            let shading_type = match change {
+                LineChange::Added => {
+                    added += 1;
+                    highlighting::Type::DiffAdded
+                }
+                LineChange::Changed => {
+                    changed += 1;
+                    highlighting::Type::DiffChanged
+                }
+                LineChange::Unchanged => continue,
+            };
+            if let Some(row) = self.document.row_mut(y) {
+                let len = row.len();
+                row.shade(0, len, shading_type);
+            }
+        }
+        self.status_message = StatusMessage::info(format!(
+            "Since last save: {} added, {} changed, {} removed",
+            added, changed, diff.removed
+        ));
+    }
+
+    /// Switch to the file related to the current one (header <-> source, module <-> test)
+    fn open_related_file(&mut self) {
+        let filename = match &self.document.filename {
+            Some(filename) => filename.clone(),
+            None => {
+                self.status_message =
+                    StatusMessage::info("No file to find a relative of".to_string());
+                return;
+            }
+        };
+        let related = FileType::related_files(&filename)
+            .into_iter()
+            .find(|candidate| std::path::Path::new(candidate).is_file());
+        let related = match related {
+            Some(related) => related,
+            None => {
+                self.status_message =
+                    StatusMessage::info(format!("No related file found for {}", filename));
+                return;
+            }
+        };
+        if self.document.is_dirty() {
+            match self.prompt("Discard unsaved changes? (Y/N)") {
+                Ok(answer) if answer.to_lowercase() == "y" => (),
+                _ => return,
+            }
+        }
+        match self.open_document(&related) {
+            Ok(document) => {
+                self.document = document;
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+                self.jump_list.clear();
+                self.status_message = StatusMessage::info(format!("Opened {}", related));
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::error(error.to_string());
+            }
+        }
+    }
+
+    fn jump_back(&mut self) {
+        match self.jump_list.back(self.cursor_position.clone()) {
+            Some(position) => self.jump_to(position),
+            None => self.status_message = StatusMessage::info("Already at oldest jump".to_string()),
+        }
+    }
+
+    fn jump_forward(&mut self) {
+        match self.jump_list.forward() {
+            Some(position) => self.jump_to(position),
+            None => self.status_message = StatusMessage::info("Already at newest jump".to_string()),
+        }
+    }
+
+    ///
+    /// Go to the `line` or `line:column` asked for, counted from one. The column is visual,
+    /// as compilers report it, so tabs count for the columns they span.
+    ///
+    fn goto_line(&mut self) {
+        let answer = match self.prompt_or_cancel("Go to line[:column] or percentage%: ") {
+            Ok(Some(answer)) if !answer.trim().is_empty() => answer,
+            _ => return,
+        };
+        let (line, column) = match parse_goto(&answer) {
+            Some(GotoTarget::Line(line, column)) => (line, column),
+            Some(GotoTarget::Percentage(percentage)) => {
+                self.jump_to_percentage(percentage);
+                return;
+            }
+            None => {
+                self.status_message =
+                    StatusMessage::error(format!("Not a line number: {}", answer));
+                return;
+            }
+        };
+        let y = line.saturating_sub(1).min(self.last_line());
+        let tab_width = self.document.tab_width();
+        // without a column, the line's text rather than its indentation
+        let x = match (column, self.document.row(y)) {
+            (Some(column), Some(row)) => row.index_at_column(column.saturating_sub(1), tab_width),
+            (None, Some(row)) => row.text().chars().take_while(|c| c.is_whitespace()).count(),
+            _ => 0,
+        };
+        self.jump_list.push(self.cursor_position.clone());
+        self.jump_to(Position { x, y });
+    }
+
+    /// Move to the line `percentage` percent through the document
+    fn jump_to_percentage(&mut self, percentage: usize) {
+        let y = self.last_line() * percentage.min(100) / 100;
+        self.jump_list.push(self.cursor_position.clone());
+        self.jump_to(Position { x: 0, y });
+    }
+
+    /// Undo the last step of edits, or redo the last undone one, moving the cursor to it
+    fn undo_redo(&mut self, undo: bool) {
+        // reached from the command prompt too, past the check on editing keys
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::info("The buffer is read-only".to_string());
+            return;
+        }
+        self.clear_selection();
+        let position = if undo {
+            self.document.undo()
+        } else {
+            self.document.redo()
+        };
+        match position {
+            Some(position) => self.jump_to(position),
+            None => {
+                self.status_message = StatusMessage::info(if undo {
+                    "Nothing to undo".to_string()
+                } else {
+                    "Nothing to redo".to_string()
+                });
+            }
+        }
+    }
+
+    /// Move the cursor to a remembered position, clamped to the current document
+    fn jump_to(&mut self, position: Position) {
+        let y = position.y.min(self.last_line());
+        let width = self.document.row(y).map_or(0, |row| row.len());
+        self.cursor_position = Position {
+            x: position.x.min(width),
+            y,
+        };
+        self.scroll();
+    }
+
+    /// The query compiled for searching, reporting a regular expression that doesn't compile
+    fn search_pattern(&mut self, query: &str) -> Option<Pattern> {
+        match Pattern::new(query, self.regex_search) {
+            Ok(pattern) => Some(pattern),
+            Err(error) => {
+                self.status_message = StatusMessage::error(error.to_string());
+                None
+            }
+        }
+    }
+
+    ///
+    /// Search interactively, moving between matches with the arrows.
+    /// Enter stays on the match, Esc goes back to where the search started.
+    ///
     fn search(&mut self) {
-        if let Ok(query) = self.prompt("Search: ") {
+        let start = SearchStart {
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone(),
+        };
+        let prompt = if self.regex_search {
+            "Search regex: "
+        } else {
+            "Search: "
+        };
+        if let Ok(Some(query)) = self.prompt_or_cancel(prompt) {
+            let query = match self.search_pattern(&query) {
+                Some(query) => query,
+                None => return,
+            };
             loop {
-                self.status_message =
-                    StatusMessage::from(format!("Searching '{}': (ESC | <- | ->)", &query));
+                self.status_message = StatusMessage::info(format!(
+                    "Searching '{}': (ESC | ENTER | <- | ->)",
+                    query.as_str()
+                ));
                 let _ = self.refresh_screen();
                 if let Some(event) = self.terminal.read_keypress() {
                     let current_position = self.cursor_position.clone();
@@ -411,7 +3272,7 @@ impl Editor {
                                 current_position.clone(),
                                 SearchDirection::Backward,
                             ) {
-                                self.cursor_position = position;
+                                self.cursor_position = position.1;
                                 self.scroll();
                             }
                         }
@@ -421,14 +3282,26 @@ impl Editor {
                                 current_position.clone(),
                                 SearchDirection::Forward,
                             ) {
-                                self.cursor_position = position;
+                                self.cursor_position = position.1;
                                 self.scroll();
                             }
                         }
+                        InputEvent::Keyboard(KeyEvent::Enter) => {
+                            self.status_message = StatusMessage::info("".to_string());
+                            self.document.search_string = Option::None;
+                            self.document.hightlight();
+                            if let Some(position) =
+                                start.finish(true, &mut self.cursor_position, &mut self.offset)
+                            {
+                                self.jump_list.push(position);
+                            }
+                            break;
+                        }
                         InputEvent::Keyboard(KeyEvent::Esc) => {
-                            self.status_message = StatusMessage::from("".to_string());
+                            self.status_message = StatusMessage::info("".to_string());
                             self.document.search_string = Option::None;
                             self.document.hightlight();
+                            start.finish(false, &mut self.cursor_position, &mut self.offset);
                             break;
                         }
                         _ => (),
@@ -438,12 +3311,460 @@ impl Editor {
         }
     }
 
+    /// Move to the start of the next or previous merge conflict
+    fn goto_conflict(&mut self, direction: SearchDirection) {
+        let y = self.cursor_position.y;
+        let conflicts = self.document.conflicts();
+        let found = match direction {
+            SearchDirection::Forward => conflicts.iter().position(|conflict| conflict.start > y),
+            SearchDirection::Backward => conflicts.iter().rposition(|conflict| conflict.start < y),
+        };
+        match found {
+            Some(index) => {
+                let start = conflicts[index].start;
+                self.status_message =
+                    StatusMessage::info(format!("Conflict {} of {}", index + 1, conflicts.len()));
+                self.jump_list.push(self.cursor_position.clone());
+                self.jump_to(Position { x: 0, y: start });
+            }
+            None => {
+                self.status_message = StatusMessage::info("No more conflicts".to_string());
+            }
+        }
+    }
+
+    /// Jump to the next line wider than the configured limit, where it overflows
+    fn goto_long_line(&mut self) {
+        let limit = self.config.max_line_length;
+        match self.document.next_long_line(self.cursor_position.y, limit) {
+            Some(y) => {
+                let tab_width = self.document.tab_width();
+                let x = self
+                    .document
+                    .row(y)
+                    .map_or(0, |row| row.index_at_column(limit, tab_width));
+                self.status_message =
+                    StatusMessage::info(format!("Line {} exceeds {} columns", y + 1, limit));
+                self.jump_list.push(self.cursor_position.clone());
+                self.jump_to(Position { x, y });
+            }
+            None => {
+                self.status_message = StatusMessage::info("No long lines".to_string());
+            }
+        }
+    }
+
+    /// Resolve the merge conflict under the cursor, keeping our side, theirs or both
+    fn resolve_conflict(&mut self, resolution: Resolution) {
+        self.clear_selection();
+        match self
+            .document
+            .resolve_conflict(self.cursor_position.y, resolution)
+        {
+            Some(y) => {
+                self.jump_to(Position { x: 0, y });
+                let left = self.document.conflicts().len();
+                self.status_message =
+                    StatusMessage::info(format!("Conflict resolved, {} left", left));
+            }
+            None => {
+                self.status_message =
+                    StatusMessage::info("The cursor is not in a conflict".to_string());
+            }
+        }
+    }
+
+    /// Move to the next or previous match of the last query searched in this buffer
+    fn search_again(&mut self, direction: SearchDirection) {
+        let query = match self.document.last_search().cloned() {
+            Some(query) => query,
+            None => {
+                self.status_message = StatusMessage::info("No previous search".to_string());
+                return;
+            }
+        };
+        if self.document.is_empty() {
+            return;
+        }
+        let mut found = self
+            .document
+            .find(&query, self.cursor_position.clone(), direction);
+        if found.is_none() && self.config.search_wrap_around {
+            let from = if direction == SearchDirection::Forward {
+                Position::default()
+            } else {
+                // the cursor stands after a match, so start past the end for the last one to count
+                let y = self.last_line();
+                let x = self.document.row(y).map_or(0, |row| row.len()) + 1;
+                Position { x, y }
+            };
+            found = self.document.find(&query, from, direction);
+            if found.is_some() {
+                self.status_message = StatusMessage::info("Search wrapped around".to_string())
+                    .with_timeout(Duration::from_secs(2));
+            }
+        }
+        match found {
+            Some((_, end)) => {
+                self.jump_list.push(self.cursor_position.clone());
+                self.jump_to(end);
+            }
+            None => {
+                self.status_message =
+                    StatusMessage::info(format!("No more matches for '{}'", query.as_str()));
+            }
+        }
+    }
+
     fn die<T>(&self, error: T, errnum: i32)
     where
         T: std::fmt::Display,
     {
         self.terminal.clear_screen();
+        // exiting skips dropping the terminal
+        self.terminal.disable_mouse();
         println!("{}", error);
         std::process::exit(errnum);
     }
 }
+
+///
+/// The nearest directory holding a project marker (`.git`, `Cargo.toml`...), starting from
+/// `path` itself when it is a directory or from its parent otherwise.
+///
+fn find_project_root(path: &Path) -> Option<PathBuf> {
+    let path = std::fs::canonicalize(path).ok().or_else(|| {
+        // a file not created yet, look from its directory
+        let parent = path.parent()?;
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        std::fs::canonicalize(parent).ok()
+    })?;
+    let mut directory = if path.is_dir() {
+        Some(path.as_path())
+    } else {
+        path.parent()
+    };
+    while let Some(dir) = directory {
+        if PROJECT_MARKERS
+            .iter()
+            .any(|marker| dir.join(marker).exists())
+        {
+            return Some(dir.to_path_buf());
+        }
+        directory = dir.parent();
+    }
+    None
+}
+
+/// Open a file within the size limits of the configuration, with the command line settings over its own
+fn open_file(
+    filename: &str,
+    config: &Config,
+    options: &CliOptions,
+) -> Result<Document, EditorError> {
+    let mut document = Document::options()
+        .read_only_above(config.read_only_file_size)
+        .max_size(config.max_file_size)
+        .open(filename)?;
+    document.set_default_indentation(config.tab_width, config.soft_tabs);
+    options.apply(&mut document);
+    Ok(document)
+}
+
+/// An empty document indented the way the command line, or else the configuration, asks
+fn new_document(config: &Config, options: &CliOptions) -> Document {
+    let mut document = Document::default();
+    document.set_default_indentation(config.tab_width, config.soft_tabs);
+    options.apply(&mut document);
+    document
+}
+
+///
+/// The first line of `text` cut to `width` characters, ending with an ellipsis when anything
+/// was left out
+///
+fn ellipsize(text: &str, width: usize) -> String {
+    let first_line = text.lines().next().unwrap_or("");
+    let len = first_line.chars().count();
+    if len <= width && first_line.len() == text.len() {
+        return text.to_string();
+    }
+    let kept = if len < width {
+        len
+    } else {
+        width.saturating_sub(1)
+    };
+    let mut result: String = first_line.chars().take(kept).collect();
+    if width > 0 {
+        result.push('…');
+    }
+    result
+}
+
+/// Split a message on its new lines and then every `width` characters
+fn wrap_message(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        for chunk in chars.chunks(width) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+    lines
+}
+
+/// Whether the key changes the document
+fn is_edit(key: KeyEvent) -> bool {
+    match key {
+        KeyEvent::Char(_)
+        | KeyEvent::Enter
+        | KeyEvent::Tab
+        | KeyEvent::Backspace
+        | KeyEvent::Delete
+        | KeyEvent::Ctrl('X')
+        | KeyEvent::Ctrl('H')
+        | KeyEvent::Ctrl('K')
+        | KeyEvent::Alt('K')
+        | KeyEvent::Ctrl('V')
+        | KeyEvent::Alt('V')
+        | KeyEvent::Ctrl('E')
+        | KeyEvent::Ctrl('S')
+        | KeyEvent::Ctrl('_')
+        | KeyEvent::Alt('y')
+        | KeyEvent::Alt('q')
+        | KeyEvent::Ctrl('Z')
+        | KeyEvent::Ctrl('Y')
+        | KeyEvent::Alt('R')
+        | KeyEvent::Alt('E')
+        | KeyEvent::Alt('a')
+        | KeyEvent::Alt('=')
+        | KeyEvent::Alt('c')
+        | KeyEvent::Alt('O')
+        | KeyEvent::Alt('T')
+        | KeyEvent::Alt('B')
+        | KeyEvent::Alt('l')
+        | KeyEvent::Alt('L')
+        | KeyEvent::Alt('s')
+        | KeyEvent::Alt('d')
+        | KeyEvent::Alt('j')
+        | KeyEvent::Alt('k')
+        | KeyEvent::Alt('w') => true,
+        _ => false,
+    }
+}
+
+/// The character closing the pair opened by `c`, if `c` opens one
+fn closing_pair(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '<' => Some('>'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        '`' => Some('`'),
+        _ => None,
+    }
+}
+
+/// Whether the key moves the cursor up or down, keeping the goal column
+fn is_vertical_move(key: KeyEvent) -> bool {
+    match key {
+        KeyEvent::Up
+        | KeyEvent::Down
+        | KeyEvent::PageUp
+        | KeyEvent::PageDown
+        | KeyEvent::ShiftUp
+        | KeyEvent::ShiftDown => true,
+        _ => false,
+    }
+}
+
+/// The command typed at the command prompt, `None` when it's not one
+fn parse_command(text: &str) -> Option<Command> {
+    let text = text.trim();
+    if let Some(query) = text.strip_prefix('/') {
+        return Some(Command::Search(Some(query.to_string())));
+    }
+    let (name, argument) = match text.find(' ') {
+        Some(index) => (&text[..index], text[index + 1..].trim()),
+        None => (text, ""),
+    };
+    let argument = if argument.is_empty() {
+        None
+    } else {
+        Some(argument.to_string())
+    };
+    Some(match (name, argument) {
+        ("", None) => Command::Cancel,
+        ("w", None) | ("write", None) => Command::Save,
+        ("q", None) | ("quit", None) => Command::Quit,
+        ("q!", None) | ("quit!", None) => Command::ForceQuit,
+        ("wq", None) | ("x", None) => Command::SaveAndQuit,
+        ("search", query) => Command::Search(query),
+        ("undo", None) => Command::Execute(|editor| editor.undo_redo(true)),
+        ("redo", None) => Command::Execute(|editor| editor.undo_redo(false)),
+        ("numbers", None) => Command::Execute(|editor| {
+            editor.show_line_numbers = !editor.show_line_numbers;
+            editor.scroll();
+        }),
+        _ => return None,
+    })
+}
+
+/// Where the goto prompt was asked to go
+#[derive(Debug, PartialEq)]
+enum GotoTarget {
+    /// A line and maybe a column, counted from one
+    Line(usize, Option<usize>),
+    /// A line this many percent through the document
+    Percentage(usize),
+}
+
+/// Parse `line`, `line:column`, `:line:column` or `percentage%`
+fn parse_goto(text: &str) -> Option<GotoTarget> {
+    let text = text.trim();
+    if let Some(percentage) = text.strip_suffix('%') {
+        let percentage = percentage.trim().parse().ok()?;
+        return Some(GotoTarget::Percentage(percentage));
+    }
+    // `:line:column`, as compilers print it after a file name
+    let text = text.strip_prefix(':').unwrap_or(text);
+    let mut parts = text.splitn(2, ':');
+    let line = parts.next()?.trim().parse().ok()?;
+    let column = match parts.next() {
+        Some(column) => Some(column.trim().parse().ok()?),
+        None => None,
+    };
+    Some(GotoTarget::Line(line, column))
+}
+
+/// Whether the key can be repeated by a count prefix
+fn is_repeatable(key: KeyEvent) -> bool {
+    match key {
+        KeyEvent::Ctrl('Z')
+        | KeyEvent::Ctrl('Y')
+        | KeyEvent::Char(_)
+        | KeyEvent::Enter
+        | KeyEvent::Tab
+        | KeyEvent::Backspace
+        | KeyEvent::Delete
+        | KeyEvent::Left
+        | KeyEvent::Right
+        | KeyEvent::Home
+        | KeyEvent::End
+        | KeyEvent::ShiftLeft
+        | KeyEvent::ShiftRight
+        | KeyEvent::CtrlLeft
+        | KeyEvent::CtrlRight
+        | KeyEvent::Alt('d')
+        | KeyEvent::Ctrl('E')
+        | KeyEvent::Alt('k')
+        | KeyEvent::Alt('j')
+        | KeyEvent::Alt('K')
+        | KeyEvent::Alt('n')
+        | KeyEvent::Alt('N') => true,
+        _ => is_vertical_move(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search_start() -> SearchStart {
+        SearchStart {
+            cursor_position: Position { x: 3, y: 10 },
+            offset: Position { x: 0, y: 5 },
+        }
+    }
+
+    #[test]
+    fn cancelled_search_goes_back() {
+        let mut cursor_position = Position { x: 7, y: 400 };
+        let mut offset = Position { x: 2, y: 380 };
+        let jump = search_start().finish(false, &mut cursor_position, &mut offset);
+        assert_eq!(jump, None);
+        assert_eq!(cursor_position, Position { x: 3, y: 10 });
+        assert_eq!(offset, Position { x: 0, y: 5 });
+    }
+
+    #[test]
+    fn accepted_search_keeps_the_jump() {
+        let mut cursor_position = Position { x: 7, y: 400 };
+        let mut offset = Position { x: 2, y: 380 };
+        let jump = search_start().finish(true, &mut cursor_position, &mut offset);
+        assert_eq!(jump, Some(Position { x: 3, y: 10 }));
+        assert_eq!(cursor_position, Position { x: 7, y: 400 });
+        assert_eq!(offset, Position { x: 2, y: 380 });
+
+        // nothing to jump back to when the cursor didn't move
+        let mut cursor_position = Position { x: 3, y: 10 };
+        let jump = search_start().finish(true, &mut cursor_position, &mut offset);
+        assert_eq!(jump, None);
+    }
+
+    #[test]
+    fn goto_lines_columns_and_percentages() {
+        assert_eq!(parse_goto("12"), Some(GotoTarget::Line(12, None)));
+        assert_eq!(parse_goto(" 12:5 "), Some(GotoTarget::Line(12, Some(5))));
+        assert_eq!(parse_goto(":12:5"), Some(GotoTarget::Line(12, Some(5))));
+        assert_eq!(parse_goto("45%"), Some(GotoTarget::Percentage(45)));
+        assert_eq!(parse_goto("45 %"), Some(GotoTarget::Percentage(45)));
+        assert_eq!(parse_goto("%"), None);
+        assert_eq!(parse_goto("12:x"), None);
+        assert_eq!(parse_goto("x%"), None);
+    }
+
+    #[test]
+    fn command_line_options_leave_the_files() {
+        let mut args: Vec<String> = [
+            "editor",
+            "-R",
+            "a.rs",
+            "--tab-width=2",
+            "--eol=crlf",
+            "b.rs",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let (options, warnings) = CliOptions::parse(&mut args);
+        assert_eq!(args, ["editor", "a.rs", "b.rs"]);
+        assert!(warnings.is_empty());
+        assert!(options.read_only);
+        assert_eq!(options.tab_width, Some(2));
+        assert_eq!(options.end_of_line, Some(EndOfLine::CrLf));
+
+        let mut args: Vec<String> = ["--tab-width=0", "--eol=x", "--nope"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let (_, warnings) = CliOptions::parse(&mut args);
+        assert!(args.is_empty());
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn command_line_options_win_over_editorconfig() {
+        let mut document = Document::default();
+        document.editorconfig.tab_width = Some(8);
+        document.editorconfig.end_of_line = Some(EndOfLine::Lf);
+        let options = CliOptions {
+            tab_width: Some(3),
+            end_of_line: Some(EndOfLine::Cr),
+            ..CliOptions::default()
+        };
+        options.apply(&mut document);
+        assert_eq!(document.tab_width(), 3);
+        assert_eq!(document.end_of_line(), EndOfLine::Cr);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EndOfLine {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl EndOfLine {
+    /// The line ending named `lf`, `crlf` or `cr`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "lf" => Some(EndOfLine::Lf),
+            "crlf" => Some(EndOfLine::CrLf),
+            "cr" => Some(EndOfLine::Cr),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for EndOfLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EndOfLine::Lf => write!(f, "LF"),
+            EndOfLine::CrLf => write!(f, "CRLF"),
+            EndOfLine::Cr => write!(f, "CR"),
+        }
+    }
+}
+
+///
+/// The `.editorconfig` properties applying to a file.
+/// A property left unset by every matching section is `None`.
+///
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct EditorConfig {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub end_of_line: Option<EndOfLine>,
+    pub insert_final_newline: Option<bool>,
+    pub trim_trailing_whitespace: Option<bool>,
+}
+
+impl EditorConfig {
+    ///
+    /// Find the `.editorconfig` files from the file's directory up to the root
+    /// (or to one declaring `root = true`) and merge the sections matching the file.
+    /// Closer files win over the ones further up.
+    ///
+    pub fn for_file(file_name: &str) -> Self {
+        let path = match fs::canonicalize(file_name) {
+            Ok(path) => path,
+            Err(_) => Path::new(file_name).to_path_buf(),
+        };
+        let mut config_files = Vec::new();
+        let mut directory = path.parent();
+        while let Some(dir) = directory {
+            let candidate = dir.join(".editorconfig");
+            if let Ok(contents) = fs::read_to_string(&candidate) {
+                let is_root = is_root(&contents);
+                config_files.push((dir.to_path_buf(), contents));
+                if is_root {
+                    break;
+                }
+            }
+            directory = dir.parent();
+        }
+
+        let mut config = Self::default();
+        for (dir, contents) in config_files.iter().rev() {
+            let relative = match path.strip_prefix(dir) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            config.apply(contents, &relative);
+        }
+        config
+    }
+
+    /// Apply the properties of every section of `contents` matching `relative_path`
+    fn apply(&mut self, contents: &str, relative_path: &str) {
+        let mut section_matches = false;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section_matches = section_matches_path(&line[1..line.len() - 1], relative_path);
+                continue;
+            }
+            if !section_matches {
+                continue;
+            }
+            if let Some((key, value)) = split_property(line) {
+                self.set(&key, &value);
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "indent_style" => {
+                self.indent_style = match value {
+                    "tab" => Some(IndentStyle::Tab),
+                    "space" => Some(IndentStyle::Space),
+                    _ => None,
+                }
+            }
+            "indent_size" => self.indent_size = value.parse().ok(),
+            "tab_width" => self.tab_width = value.parse().ok(),
+            "end_of_line" => self.end_of_line = EndOfLine::from_name(value),
+            "insert_final_newline" => self.insert_final_newline = parse_bool(value),
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_bool(value),
+            _ => (),
+        }
+    }
+}
+
+fn is_root(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(split_property)
+        .any(|(key, value)| key == "root" && value == "true")
+}
+
+/// `key = value`, both lower cased as the spec says they are case insensitive
+fn split_property(line: &str) -> Option<(String, String)> {
+    let mut parts = line.splitn(2, |c| c == '=' || c == ':');
+    let key = parts.next()?.trim().to_lowercase();
+    let value = parts.next()?.trim().to_lowercase();
+    Some((key, value))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+///
+/// Whether a section glob applies to the path (relative to the `.editorconfig` directory).
+/// Globs without a slash match the file name in any directory.
+///
+fn section_matches_path(glob: &str, relative_path: &str) -> bool {
+    let glob = if glob.contains('/') {
+        glob.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", glob)
+    };
+    expand_braces(&glob)
+        .iter()
+        .any(|pattern| glob_matches(pattern.as_bytes(), relative_path.as_bytes()))
+}
+
+/// `*.{js,py}` becomes `*.js` and `*.py`
+fn expand_braces(glob: &str) -> Vec<String> {
+    let open = match glob.find('{') {
+        Some(open) => open,
+        None => return vec![glob.to_string()],
+    };
+    let close = match glob[open..].find('}') {
+        Some(close) => open + close,
+        None => return vec![glob.to_string()],
+    };
+    let (prefix, suffix) = (&glob[..open], &glob[close + 1..]);
+    glob[open + 1..close]
+        .split(',')
+        .flat_map(|alternative| expand_braces(&format!("{}{}{}", prefix, alternative, suffix)))
+        .collect()
+}
+
+/// `*` matches within a directory, `**` across directories, `?` any single character
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            // `**/` may also match no directory at all
+            if rest.first() == Some(&b'/') && glob_matches(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|skip| glob_matches(rest, &text[skip..]))
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for skip in 0..=text.len() {
+                if glob_matches(rest, &text[skip..]) {
+                    return true;
+                }
+                if text.get(skip) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(c) if *c != b'/' => glob_matches(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(c) => text.first() == Some(c) && glob_matches(&pattern[1..], &text[1..]),
+    }
+}
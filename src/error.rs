@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::fmt;
+use std::string::FromUtf8Error;
+
+///
+/// Failures of the editor, with the file or operation they concern.
+///
+#[derive(Debug)]
+pub enum EditorError {
+    /// Reading or writing a file failed, `operation` says what was being done ("read", "write"...)
+    Io {
+        operation: &'static str,
+        path: String,
+        source: std::io::Error,
+    },
+    /// The file is not valid UTF-8
+    Utf8 { path: String, source: FromUtf8Error },
+    /// The file holds NUL bytes and is most likely not text
+    Binary { path: String },
+    /// The file is larger than the editor agrees to load
+    TooLarge { path: String, size: u64, limit: u64 },
+    /// An external command (e.g. a formatter) exited with an error
+    Command { command: String, stderr: String },
+    /// A search query is not a valid regular expression
+    Regex { query: String, source: regex::Error },
+    /// The terminal could not be set up
+    Terminal {
+        operation: &'static str,
+        source: Box<dyn Error>,
+    },
+}
+
+impl EditorError {
+    /// Wrap an IO error with the operation and file it happened on
+    pub fn io(operation: &'static str, path: &str, source: std::io::Error) -> Self {
+        EditorError::Io {
+            operation,
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditorError::Io {
+                operation,
+                path,
+                source,
+            } => write!(f, "Could not {} {}: {}", operation, path, source),
+            EditorError::Utf8 { path, source } => {
+                write!(f, "{} is not valid UTF-8: {}", path, source)
+            }
+            EditorError::Binary { path } => write!(f, "{} looks like a binary file", path),
+            EditorError::TooLarge { path, size, limit } => write!(
+                f,
+                "{} is too large to open ({} bytes, the limit is {})",
+                path, size, limit
+            ),
+            EditorError::Command { command, stderr } => {
+                write!(f, "{} failed: {}", command, stderr.trim_end())
+            }
+            EditorError::Regex { query, source } => {
+                write!(f, "Invalid regular expression {}: {}", query, source)
+            }
+            EditorError::Terminal { operation, source } => {
+                write!(f, "Could not {}: {}", operation, source)
+            }
+        }
+    }
+}
+
+impl Error for EditorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EditorError::Io { source, .. } => Some(source),
+            EditorError::Utf8 { source, .. } => Some(source),
+            EditorError::Regex { source, .. } => Some(source),
+            EditorError::Binary { .. }
+            | EditorError::TooLarge { .. }
+            | EditorError::Command { .. } => None,
+            EditorError::Terminal { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
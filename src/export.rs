@@ -0,0 +1,119 @@
+use crate::document::Document;
+use crate::highlighting;
+use crate::terminal::Color;
+
+///
+/// Render the document as a standalone HTML page, keeping the syntax colors
+/// shown on screen. Each run of characters with the same highlighting becomes a span.
+///
+pub fn to_html(document: &Document) -> String {
+    let title = match &document.filename {
+        Some(filename) => escape(filename),
+        None => String::from("[No name]"),
+    };
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<pre style=\"background:#1e1e1e;color:#e5e5e5;padding:1em\">",
+        title
+    );
+    // tabs expand as on screen
+    let tab_width = document.tab_width();
+    for row in &document.rows {
+        let mut column = 0;
+        let mut run = String::new();
+        let mut run_type = highlighting::Type::None;
+        for (index, character) in row.text().chars().enumerate() {
+            let highlight_type = row
+                .highlighting()
+                .get(index)
+                .cloned()
+                .unwrap_or(highlighting::Type::None);
+            if highlight_type != run_type {
+                push_span(&mut html, &run, &run_type);
+                run.clear();
+                run_type = highlight_type;
+            }
+            if character == '\t' {
+                let spaces = tab_width - column % tab_width;
+                run.push_str(&" ".repeat(spaces));
+                column += spaces;
+            } else {
+                run.push(character);
+                column += 1;
+            }
+        }
+        push_span(&mut html, &run, &run_type);
+        html.push('\n');
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+fn push_span(html: &mut String, text: &str, highlight_type: &highlighting::Type) {
+    if text.is_empty() {
+        return;
+    }
+    if *highlight_type == highlighting::Type::None {
+        html.push_str(&escape(text));
+    } else {
+        html.push_str(&format!(
+            "<span style=\"color:{}\">{}</span>",
+            css_color(highlight_type.to_color()),
+            escape(text)
+        ));
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// CSS equivalent of a terminal color (xterm's default palette)
+fn css_color(color: Color) -> String {
+    let hex = match color {
+        Color::Black => "#000000",
+        Color::DarkGrey => "#7f7f7f",
+        Color::Red => "#ff0000",
+        Color::DarkRed => "#cd0000",
+        Color::Green => "#00ff00",
+        Color::DarkGreen => "#00cd00",
+        Color::Yellow => "#ffff00",
+        Color::DarkYellow => "#cdcd00",
+        Color::Blue => "#5c5cff",
+        Color::DarkBlue => "#0000ee",
+        Color::Magenta => "#ff00ff",
+        Color::DarkMagenta => "#cd00cd",
+        Color::Cyan => "#00ffff",
+        Color::DarkCyan => "#00cdcd",
+        Color::White => "#ffffff",
+        Color::Grey => "#e5e5e5",
+        Color::Rgb { r, g, b } => return format!("#{:02x}{:02x}{:02x}", r, g, b),
+        _ => "inherit",
+    };
+    hex.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::row::Row;
+
+    #[test]
+    fn tabs_expand_to_the_document_tab_width() {
+        let mut document = Document::default();
+        document.rows = vec![Row::from("\tx"), Row::from("ab\ty")];
+        document.set_default_indentation(2, false);
+        let html = to_html(&document);
+        assert!(html.contains(">  x\n"));
+        assert!(html.contains("\nab  y\n"));
+    }
+}
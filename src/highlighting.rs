@@ -1,8 +1,21 @@
 use crate::filetype::FileType;
 use crate::filetype::HighlightingOptions;
 use crate::terminal::Color;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
 
-#[derive(Debug, PartialEq, Clone)]
+thread_local! {
+    // colors replacing the built in ones, from the user's config
+    static THEME: RefCell<HashMap<Type, Color>> = RefCell::new(HashMap::new());
+}
+
+/// Draw the given highlighting types in these colors rather than the built in ones
+pub fn set_theme(theme: HashMap<Type, Color>) {
+    THEME.with(|current| *current.borrow_mut() = theme);
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Type {
     None,
     Number,
@@ -13,12 +26,28 @@ pub enum Type {
     MultilineComment,
     PrimaryKeywords,
     SecondaryKeywords,
+    FunctionCall,
+    Operator,
     WhiteSpace,
     Punctuation,
+    Selection,
+    DiffAdded,
+    DiffChanged,
+    ConflictOurs,
+    ConflictTheirs,
+    ConflictMarker,
+    ControlCharacter,
+    UnmatchedBracket,
 }
 
 impl Type {
     pub fn to_color(&self) -> Color {
+        THEME
+            .with(|theme| theme.borrow().get(self).cloned())
+            .unwrap_or_else(|| self.default_color())
+    }
+
+    fn default_color(&self) -> Color {
         match self {
             Type::Number => {
                 return Color::Rgb {
@@ -28,16 +57,126 @@ impl Type {
                 }
             }
             Type::Match => return Color::DarkYellow,
+            Type::Selection => return Color::DarkBlue,
+            Type::DiffAdded => return Color::DarkGreen,
+            Type::DiffChanged => return Color::DarkMagenta,
+            Type::ConflictOurs => return Color::DarkGreen,
+            Type::ConflictTheirs => return Color::DarkBlue,
+            Type::ConflictMarker => return Color::DarkRed,
+            Type::ControlCharacter => return Color::Red,
+            Type::UnmatchedBracket => return Color::DarkRed,
             Type::String | Type::Character => return Color::DarkGreen,
-            Type::Comment => return Color::DarkGrey,
+            Type::Comment | Type::MultilineComment => return Color::DarkGrey,
             Type::PrimaryKeywords => return Color::DarkCyan,
             Type::SecondaryKeywords => return Color::DarkRed,
             Type::Punctuation => return Color::Magenta,
+            Type::FunctionCall => return Color::Yellow,
+            Type::Operator => return Color::Blue,
             _ => Color::White,
         }
     }
 }
 
+///
+/// What a highlighter carries from the end of a line to the start of the next one,
+/// e.g. being inside a multiline comment. Each highlighter gives the number its own meaning,
+/// the default state means nothing is left open.
+///
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct State(pub u32);
+
+///
+/// Splits lines into highlighted ranges, implement it to highlight a language the editor does not know.
+///
+pub trait Highlighter {
+    ///
+    /// Highlight `line`, starting in the state the previous line ended in.
+    /// Ranges are in characters, characters no range covers are not highlighted.
+    /// Returns the ranges and the state the line ends in.
+    ///
+    fn highlight(&self, line: &str, start_state: State) -> (Vec<(Range<usize>, Type)>, State);
+}
+
+///
+/// The built in highlighter: splits lines into tokens typed with the filetype's highlighting options
+///
+pub struct TokenHighlighter {
+    filetype: FileType,
+}
+
+impl TokenHighlighter {
+    pub fn new(filetype: FileType) -> Self {
+        Self { filetype }
+    }
+}
+
+impl TokenHighlighter {
+    /// The state of a line ending inside a `/* */` comment
+    const IN_COMMENT: State = State(1);
+
+    /// Tokenize the code in `chars`, which starts at character `offset` of the line
+    fn push_tokens(&self, chars: &[char], offset: usize, ranges: &mut Vec<(Range<usize>, Type)>) {
+        let code: String = chars.iter().collect();
+        let mut start = offset;
+        for token in Token::tokenize(&self.filetype, &code) {
+            let end = start + token.value.chars().count();
+            ranges.push((start..end, token.token_type));
+            start = end;
+        }
+    }
+}
+
+impl Highlighter for TokenHighlighter {
+    fn highlight(&self, line: &str, start_state: State) -> (Vec<(Range<usize>, Type)>, State) {
+        let multiline_comments = self.filetype.highlighting_options().multiline_comments();
+        let chars: Vec<char> = line.chars().collect();
+        let mut ranges = Vec::new();
+        let mut in_comment = multiline_comments && start_state == Self::IN_COMMENT;
+        let mut in_string = false;
+        // start of the comment or code not pushed yet
+        let mut segment_start = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            let next = chars.get(i + 1).cloned();
+            if in_comment {
+                if chars[i] == '*' && next == Some('/') {
+                    i += 2;
+                    ranges.push((segment_start..i, Type::MultilineComment));
+                    segment_start = i;
+                    in_comment = false;
+                    continue;
+                }
+            } else if !in_string && chars[i] == '\'' {
+                // a character literal (`'"'`, `'\''`), a lone quote is a lifetime
+                let len = if next == Some('\\') { 4 } else { 3 };
+                if chars.get(i + len - 1) == Some(&'\'') {
+                    i += len;
+                    continue;
+                }
+            } else if chars[i] == '"' && (i == 0 || chars[i - 1] != '\\') {
+                in_string = !in_string;
+            } else if !in_string && chars[i] == '/' && next == Some('/') {
+                // a line comment, the tokenizer takes it from here
+                break;
+            } else if !in_string && multiline_comments && chars[i] == '/' && next == Some('*') {
+                self.push_tokens(&chars[segment_start..i], segment_start, &mut ranges);
+                segment_start = i;
+                in_comment = true;
+                i += 2;
+                continue;
+            }
+            i += 1;
+        }
+        if in_comment {
+            ranges.push((segment_start..chars.len(), Type::MultilineComment));
+            (ranges, Self::IN_COMMENT)
+        } else {
+            self.push_tokens(&chars[segment_start..], segment_start, &mut ranges);
+            (ranges, State::default())
+        }
+    }
+}
+
 pub struct Token {
     pub value: String,
     pub token_type: Type,
@@ -138,12 +277,18 @@ impl Token {
         let mut found_string = false;
         let mut found_char = false;
         let highlighting_options = filetype.highlighting_options();
-        for (i, c) in string.chars().enumerate() {
-            if c.is_ascii_punctuation() || c.is_ascii_whitespace() {
-                // this is a comment
-                if previous_char == '/' && c == '/' {
-                    buffer = string[(i - 1)..].to_string();
+        // byte offsets, so slicing the line is safe with multibyte characters
+        let mut chars = string.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if is_separator(c) {
+                // this is a comment, up to the end of the line
+                if c == '/' && chars.peek().map(|(_, next)| *next) == Some('/') && !found_string {
+                    if !buffer.is_empty() {
+                        tokens.push(Token::from(highlighting_options, buffer));
+                    }
+                    buffer = string[i..].to_string();
                     tokens.push(Token::from(highlighting_options, buffer));
+                    buffer = String::new();
                     break;
                 }
                 // parse strings
@@ -171,7 +316,7 @@ impl Token {
                     }
                 }
                 // any other punctuation should be considered end of a token and start of a new.
-                else if c != '/' {
+                else {
                     tokens.push(Token::from(highlighting_options, buffer));
                     buffer = String::new();
                     buffer.push(c);
@@ -180,7 +325,7 @@ impl Token {
                 }
             }
             // End of line
-            else if c == '\n' || c == '\r' || i == string.len() - 1 {
+            else if c == '\n' || c == '\r' {
                 buffer.push(c);
                 tokens.push(Token::from(highlighting_options, buffer));
                 buffer = String::new();
@@ -189,9 +334,85 @@ impl Token {
             }
             previous_char = c;
         }
+        // the last word (or an unterminated string) when the line does not end with a separator
+        if !buffer.is_empty() {
+            tokens.push(Token::from(highlighting_options, buffer));
+        }
+        if highlighting_options.operators() {
+            tokens = Token::merge_operators(tokens);
+        }
+        if highlighting_options.function_calls() {
+            Token::mark_function_calls(&mut tokens);
+        }
         tokens
     }
 
+    ///
+    /// Retype the punctuation that makes operators, joining the two character ones
+    /// (`==`, `<=`, `&&`, `+=`...) the tokenizer split into a single token
+    ///
+    fn merge_operators(tokens: Vec<Token>) -> Vec<Token> {
+        let is_operator_token = |token: &Token| {
+            (token.token_type == Type::Punctuation || token.token_type == Type::None)
+                && token.value.chars().count() == 1
+                && token.value.chars().all(is_operator)
+        };
+        let mut merged: Vec<Token> = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            // the tokenizer leaves empty tokens between consecutive punctuation
+            if token.value.is_empty() {
+                continue;
+            }
+            if !is_operator_token(&token) {
+                merged.push(token);
+                continue;
+            }
+            if let Some(previous) = merged.last_mut() {
+                if previous.token_type == Type::Operator && previous.value.chars().count() == 1 {
+                    let pair = format!("{}{}", previous.value, token.value);
+                    if TWO_CHARACTER_OPERATORS.contains(&pair.as_str()) {
+                        previous.value = pair;
+                        continue;
+                    }
+                }
+            }
+            merged.push(Token {
+                value: token.value,
+                token_type: Type::Operator,
+            });
+        }
+        merged
+    }
+
+    ///
+    /// Retype the plain identifiers directly followed by `(` as function calls,
+    /// and the ones followed by `!(` as macro calls
+    ///
+    fn mark_function_calls(tokens: &mut Vec<Token>) {
+        // the tokenizer leaves empty tokens between consecutive punctuation
+        let significant: Vec<usize> = (0..tokens.len())
+            .filter(|&i| !tokens[i].value.is_empty())
+            .collect();
+        for (n, &i) in significant.iter().enumerate() {
+            if tokens[i].token_type != Type::None || !is_identifier(&tokens[i].value) {
+                continue;
+            }
+            let next = |offset: usize| {
+                significant
+                    .get(n + offset)
+                    .map(|&j| tokens[j].value.as_str())
+            };
+            let is_call = match next(1) {
+                Some("(") => true,
+                Some("!") => next(2) == Some("("),
+                _ => false,
+            };
+            if is_call {
+                tokens[i].token_type = Type::FunctionCall;
+            }
+        }
+    }
+
     /// Convert token to string, this also adds the proper coloring to the token
     /// For matches, the foreground color is set.
     pub fn to_string(&self) -> String {
@@ -212,3 +433,89 @@ impl Token {
         }
     }
 }
+
+/// The operators spanning two characters, the tokenizer splitting them in two
+const TWO_CHARACTER_OPERATORS: [&str; 18] = [
+    "==", "!=", "<=", ">=", "=>", "->", "&&", "||", "<<", ">>", "+=", "-=", "*=", "/=", "%=", "^=",
+    "&=", "|=",
+];
+
+/// Whether the character alone is an operator
+fn is_operator(c: char) -> bool {
+    match c {
+        '+' | '-' | '*' | '/' | '%' | '=' | '<' | '>' | '!' | '&' | '|' | '^' | '~' | '?' => true,
+        _ => false,
+    }
+}
+
+/// Whether the character ends a word for the tokenizer: ASCII whitespace and punctuation
+pub fn is_separator(c: char) -> bool {
+    c.is_ascii_punctuation() || c.is_ascii_whitespace()
+}
+
+/// Whether `word` could name a function: letters, digits and underscores, not starting with a digit
+fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) if first.is_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Vec<(String, Type)> {
+        Token::tokenize(&FileType::from("main.rs"), &line.to_string())
+            .into_iter()
+            .map(|token| (token.value, token.token_type))
+            .collect()
+    }
+
+    #[test]
+    fn keep_a_trailing_identifier() {
+        let tokens = tokens("let x = value");
+        let text: String = tokens.iter().map(|(value, _)| value.as_str()).collect();
+        assert_eq!(text, "let x = value");
+        assert_eq!(tokens.last().unwrap().0, "value");
+        assert_eq!(tokens[0], ("let".to_string(), Type::PrimaryKeywords));
+    }
+
+    #[test]
+    fn keep_trailing_multibyte_characters() {
+        let tokens = tokens("let café = naïveté");
+        let text: String = tokens.iter().map(|(value, _)| value.as_str()).collect();
+        assert_eq!(text, "let café = naïveté");
+        assert_eq!(tokens.last().unwrap().0, "naïveté");
+        assert_eq!(self::tokens("→").last().unwrap().0, "→");
+    }
+
+    #[test]
+    fn quote_character_does_not_hide_a_comment() {
+        let highlighter = TokenHighlighter::new(FileType::from("main.rs"));
+        let comment = |line: &str| {
+            let (ranges, state) = highlighter.highlight(line, State::default());
+            let comments: Vec<Range<usize>> = ranges
+                .into_iter()
+                .filter(|(_, token_type)| *token_type == Type::MultilineComment)
+                .map(|(range, _)| range)
+                .collect();
+            (comments, state)
+        };
+        assert_eq!(
+            comment("let q = '\"'; /* c */"),
+            (vec![13..20], State::default())
+        );
+        assert_eq!(
+            comment("let q = '\\''; /* c"),
+            (vec![14..18], TokenHighlighter::IN_COMMENT)
+        );
+        assert_eq!(
+            comment("fn f<'a>(x: &'a str) /* c */"),
+            (vec![21..28], State::default())
+        );
+    }
+}
@@ -11,4 +11,16 @@ pub mod terminal;
 pub mod document;
 pub mod row;
 pub mod filetype;
-pub mod highlighting;
\ No newline at end of file
+pub mod highlighting;
+pub mod config;
+pub mod export;
+pub mod diff;
+pub mod editorconfig;
+pub mod ansi;
+pub mod error;
+pub mod wrap;
+pub mod checksum;
+pub mod conflict;
+pub mod undo;
+pub mod clipboard;
+pub mod pattern;
\ No newline at end of file
@@ -0,0 +1,119 @@
+use crate::document::SearchDirection;
+use crate::error::EditorError;
+use regex::Regex;
+use std::ops::Range;
+
+///
+/// What a search looks for: the query as typed, or a regular expression
+///
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Text(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// The query as plain text, or compiled as a regular expression when `regex` is set
+    pub fn new(query: &str, regex: bool) -> Result<Self, EditorError> {
+        if !regex {
+            return Ok(Pattern::Text(query.to_string()));
+        }
+        Regex::new(query)
+            .map(Pattern::Regex)
+            .map_err(|source| EditorError::Regex {
+                query: query.to_string(),
+                source,
+            })
+    }
+
+    /// The query the pattern was made from
+    pub fn as_str(&self) -> &str {
+        match self {
+            Pattern::Text(query) => query,
+            Pattern::Regex(regex) => regex.as_str(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_str().is_empty()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.matches(text).next().is_some()
+    }
+
+    ///
+    /// The character range of the next match in `text`: going forward the first one starting
+    /// at `start` or after, going backward the last one ending before `start`.
+    /// Empty matches (e.g. of `a*`) are skipped, they can't be shown nor moved to.
+    ///
+    pub fn find(
+        &self,
+        text: &str,
+        start: usize,
+        direction: SearchDirection,
+    ) -> Option<Range<usize>> {
+        match direction {
+            SearchDirection::Forward => {
+                let from = text
+                    .char_indices()
+                    .map(|(index, _)| index)
+                    .chain(Some(text.len()))
+                    .nth(start)?;
+                self.find_bytes(text, from)
+                    .map(|range| char_range(text, range))
+            }
+            SearchDirection::Backward => self
+                .matches(text)
+                .take_while(|range| range.end < start)
+                .last(),
+        }
+    }
+
+    /// The character ranges of the matches in `text`, in order and not overlapping
+    pub fn matches<'a>(&'a self, text: &'a str) -> impl Iterator<Item = Range<usize>> + 'a {
+        let mut from = 0;
+        std::iter::from_fn(move || {
+            let range = self.find_bytes(text, from)?;
+            from = range.end;
+            Some(char_range(text, range))
+        })
+    }
+
+    /// The byte range of the first non-empty match starting at byte `from` or after
+    fn find_bytes(&self, text: &str, from: usize) -> Option<Range<usize>> {
+        match self {
+            Pattern::Text(query) if query.is_empty() => None,
+            Pattern::Text(query) => text[from..]
+                .find(query.as_str())
+                .map(|index| from + index..from + index + query.len()),
+            Pattern::Regex(regex) => {
+                let mut from = from;
+                loop {
+                    let found = regex.find_at(text, from)?;
+                    if found.start() < found.end() {
+                        return Some(found.start()..found.end());
+                    }
+                    // step over the empty match to the next character
+                    from = found.end() + text[found.end()..].chars().next()?.len_utf8();
+                }
+            }
+        }
+    }
+}
+
+/// A byte range of `text` as a range of characters
+fn char_range(text: &str, range: Range<usize>) -> Range<usize> {
+    let start = text[..range.start].chars().count();
+    start..start + text[range].chars().count()
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Pattern::Text(a), Pattern::Text(b)) => a == b,
+            (Pattern::Regex(a), Pattern::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
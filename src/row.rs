@@ -1,15 +1,30 @@
 use crate::document::SearchDirection;
-use crate::filetype::FileType;
 use crate::highlighting;
+use crate::highlighting::Highlighter;
+use crate::pattern::Pattern;
 use crate::terminal::Color;
+use std::cell::RefCell;
+use std::ops::Range;
+
+/// Rows longer than this (minified files, logs...) are not highlighted to keep the editor responsive
+const MAX_HIGHLIGHT_LEN: usize = 10_000;
 
 #[derive(Default, Debug)]
 pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
     shading: Vec<highlighting::Type>,
+    // highlighter state the row was last highlighted from, `None` until highlighted
+    start_state: Option<highlighting::State>,
+    // highlighter state at the end of the row, the next row starts from it
+    end_state: highlighting::State,
+    // the last rendering and the arguments it was made with, dropped whenever the row changes
+    render_cache: RefCell<Option<(RenderKey, String)>>,
 }
 
+/// The arguments of `Row::render`: start and end columns, control characters shown, tab width
+type RenderKey = (usize, usize, bool, usize);
+
 impl Row {
     pub fn new(string: String) -> Self {
         let highlighting = Vec::new();
@@ -18,33 +33,81 @@ impl Row {
             string,
             highlighting,
             shading,
+            start_state: None,
+            end_state: highlighting::State::default(),
+            render_cache: RefCell::new(None),
         }
     }
 
-    pub fn render(&self, start: usize, end: usize) -> String {
+    ///
+    /// Render the screen columns `start..end` of the row, tabs expanded to the next multiple
+    /// of `tab_width`. With `show_control`, other control characters are drawn in caret notation
+    /// (`^A`, `^M`...).
+    ///
+    pub fn render(&self, start: usize, end: usize, show_control: bool, tab_width: usize) -> String {
+        let key = (start, end, show_control, tab_width);
+        if let Some((cached_key, rendered)) = &*self.render_cache.borrow() {
+            if *cached_key == key {
+                return rendered.clone();
+            }
+        }
+        let rendered = self.render_uncached(start, end, show_control, tab_width);
+        *self.render_cache.borrow_mut() = Some((key, rendered.clone()));
+        rendered
+    }
+
+    fn render_uncached(
+        &self,
+        start: usize,
+        end: usize,
+        show_control: bool,
+        tab_width: usize,
+    ) -> String {
         let mut result = String::new();
+        let mut column = 0;
 
-        for (index, character) in self
-            .string
-            .chars()
-            .skip(start)
-            .take(end - start)
-            .enumerate()
-        {
-            let highlight_type = self
-                .highlighting
-                .get(index + start)
-                .unwrap_or(&highlighting::Type::None);
+        for (index, character) in self.string.chars().enumerate() {
+            if column >= end {
+                break;
+            }
+            let caret = if show_control && character != '\t' {
+                caret_notation(character)
+            } else {
+                None
+            };
+            let cells = match (&caret, character) {
+                (Some(caret), _) => caret.clone(),
+                (None, '\t') => " ".repeat(tab_width - column % tab_width),
+                (None, _) => character.to_string(),
+            };
+            let width = cells.chars().count();
+            if column.saturating_add(width) <= start {
+                column += width;
+                continue;
+            }
+            // a caret notation or a tab may be cut by either edge of the screen
+            let visible: String = cells
+                .chars()
+                .enumerate()
+                .filter(|(cell, _)| (start..end).contains(&(column + cell)))
+                .map(|(_, c)| c)
+                .collect();
+            column += width;
+
+            let highlight_type = if caret.is_some() {
+                &highlighting::Type::ControlCharacter
+            } else {
+                self.highlighting
+                    .get(index)
+                    .unwrap_or(&highlighting::Type::None)
+            };
             let mut colored_char = format!(
                 "{}{}{}",
                 crossterm::SetFg(highlight_type.to_color()),
-                character,
+                visible,
                 crossterm::SetFg(Color::Reset)
             );
-            let shading_type = self
-                .shading
-                .get(index + start)
-                .unwrap_or(&highlighting::Type::None);
+            let shading_type = self.shading.get(index).unwrap_or(&highlighting::Type::None);
             if shading_type != &highlighting::Type::None {
                 colored_char = format!(
                     "{}{}{}",
@@ -58,11 +121,62 @@ impl Row {
         result
     }
 
+    ///
+    /// The screen column of the character at `x`, tabs and caret notations taking more
+    /// than one column
+    ///
+    pub fn render_x(&self, x: usize, show_control: bool, tab_width: usize) -> usize {
+        let before = self.string.chars().take(x).fold(0, |column, c| {
+            let width = if c == '\t' {
+                tab_width - column % tab_width
+            } else if show_control {
+                caret_notation(c).map_or(1, |caret| caret.chars().count())
+            } else {
+                1
+            };
+            column + width
+        });
+        // past the end of the row
+        before + x.saturating_sub(self.len())
+    }
+
+    /// How many screen columns the whole row takes
+    pub fn render_width(&self, show_control: bool, tab_width: usize) -> usize {
+        self.render_x(self.len(), show_control, tab_width)
+    }
+
+    ///
+    /// The index of the character covering the visual `column` (counted from zero), tabs
+    /// reaching the next multiple of `tab_width`. Columns past the end give the row length.
+    ///
+    pub fn index_at_column(&self, column: usize, tab_width: usize) -> usize {
+        let mut visual = 0;
+        for (index, c) in self.string.chars().enumerate() {
+            let width = if c == '\t' {
+                tab_width - visual % tab_width
+            } else {
+                1
+            };
+            if column < visual + width {
+                return index;
+            }
+            visual += width;
+        }
+        self.len()
+    }
+
+    /// The length in characters, the unit of every position in the row
     pub fn len(&self) -> usize {
-        self.string.len()
+        self.string.chars().count()
+    }
+
+    /// Forget the cached rendering, the text or its colors changed
+    fn invalidate(&mut self) {
+        *self.render_cache.get_mut() = None;
     }
 
     pub fn delete(&mut self, at: usize) {
+        self.invalidate();
         let mut string = String::new();
         for (index, character) in self.string.chars().enumerate() {
             if index == at {
@@ -73,7 +187,8 @@ impl Row {
         self.string = string;
     }
     pub fn insert(&mut self, c: char, at: usize) {
-        if self.string.len() == at {
+        self.invalidate();
+        if self.len() == at {
             self.string.push(c);
             return;
         }
@@ -88,6 +203,7 @@ impl Row {
         self.string = string;
     }
     pub fn split(&mut self, pos: usize) -> Self {
+        self.invalidate();
         let mut this_string = String::new();
         let mut new_string = String::new();
         for (i, c) in self.string.chars().enumerate() {
@@ -102,62 +218,109 @@ impl Row {
             string: new_string,
             highlighting: Vec::new(),
             shading: Vec::new(),
+            start_state: None,
+            end_state: highlighting::State::default(),
+            render_cache: RefCell::new(None),
         }
     }
     pub fn append(&mut self, other_row: &Row) {
+        self.invalidate();
         self.string.push_str(&other_row.string[..]);
     }
 
     pub fn text(&self) -> &String {
         &self.string
     }
-    pub fn find(&self, query: &String, start: usize, direction: SearchDirection) -> Option<usize> {
-        match direction {
-            SearchDirection::Forward => {
-                if start >= self.string.len() {
-                    return None;
-                }
-                let new_string = self.string[start..].to_string();
-                if let Some(location) = new_string.find(query) {
-                    return Some(location + start + query.len());
-                }
-            }
-            SearchDirection::Backward => {
-                let start = start.saturating_sub(query.len());
-                let new_string = self.string[..start].to_string();
-                if let Some(location) = new_string.rfind(query) {
-                    return Some(location + query.len());
-                }
-            }
+
+    /// Forget the state the row was highlighted from, the next highlighting pass redoes it
+    pub fn mark_stale(&mut self) {
+        self.start_state = None;
+    }
+    /// The highlighting type of each character, as last computed by `highlight`
+    pub fn highlighting(&self) -> &Vec<highlighting::Type> {
+        &self.highlighting
+    }
+    ///
+    /// Find `pattern` from the character at `start` on (or ending before it going backward).
+    /// Returns the character range of the match.
+    ///
+    pub fn find(
+        &self,
+        pattern: &Pattern,
+        start: usize,
+        direction: SearchDirection,
+    ) -> Option<Range<usize>> {
+        pattern.find(&self.string, start, direction)
+    }
+    ///
+    /// Shade the characters in `start..end` with the given type (e.g. a selection)
+    /// The shading is reset next time the row is highlighted.
+    ///
+    pub fn shade(&mut self, start: usize, end: usize, shading_type: highlighting::Type) {
+        let len = self.len();
+        if self.shading.len() < len {
+            self.shading.resize(len, highlighting::Type::None);
+        }
+        let end = end.min(len);
+        // shading again what's shaded already keeps the rendering cached
+        if self.shading[start.min(end)..end]
+            .iter()
+            .all(|shading| *shading == shading_type)
+        {
+            return;
+        }
+        self.invalidate();
+        for i in start..end {
+            self.shading[i] = shading_type.clone();
         }
-        None
     }
-    pub fn highlight(&mut self, filetype: &FileType, search_word: &Option<String>) {
-        let tokens = highlighting::Token::tokenize(filetype, &self.string);
-        let mut highlighting = Vec::new();
-        let mut shading = Vec::new();
-        for token in tokens {
-            for _ in token.value.chars() {
-                highlighting.push(token.token_type.clone());
-                shading.push(highlighting::Type::None);
+    ///
+    /// Highlight the row starting in `start_state` (where the previous row ended) and shade
+    /// the matches of `search_pattern`. Returns the state the row ends in.
+    ///
+    pub fn highlight(
+        &mut self,
+        highlighter: &dyn Highlighter,
+        start_state: highlighting::State,
+        search_pattern: &Option<Pattern>,
+    ) -> highlighting::State {
+        self.invalidate();
+        let len = self.string.chars().count();
+        self.start_state = Some(start_state);
+        if self.string.len() > MAX_HIGHLIGHT_LEN {
+            self.highlighting = vec![highlighting::Type::None; len];
+            self.shading = vec![highlighting::Type::None; len];
+            self.end_state = start_state;
+            return start_state;
+        }
+        let (ranges, end_state) = highlighter.highlight(&self.string, start_state);
+        let mut highlighting = vec![highlighting::Type::None; len];
+        let mut shading = vec![highlighting::Type::None; len];
+        for (range, highlight_type) in ranges {
+            for index in range.start..range.end.min(len) {
+                highlighting[index] = highlight_type.clone();
             }
         }
-        let mut search_index = 0;
-        if let Some(word) = search_word {
-            // println!("word={}", word);
-            // std::thread::sleep_ms(500);
-            while let Some(index) =
-                self.find(&word.to_string(), search_index, SearchDirection::Forward)
-            {
-                // search_index = search_index.saturating_sub(word.len()); // returns the last index inside of the word, we subract to get the first
-                for i in index.saturating_sub(word.len())..index {
+        if let Some(pattern) = search_pattern {
+            // matches differ in length with a regex, each is shaded over its own span
+            for range in pattern.matches(&self.string) {
+                for i in range.start..range.end.min(len) {
                     shading[i] = highlighting::Type::Match;
                 }
-                search_index = index;
             }
         }
         self.highlighting = highlighting;
         self.shading = shading;
+        self.end_state = end_state;
+        end_state
+    }
+
+    pub fn start_state(&self) -> Option<highlighting::State> {
+        self.start_state
+    }
+
+    pub fn end_state(&self) -> highlighting::State {
+        self.end_state
     }
 }
 
@@ -167,6 +330,160 @@ impl From<&str> for Row {
             string: String::from(slice),
             highlighting: Vec::new(),
             shading: Vec::new(),
+            start_state: None,
+            end_state: highlighting::State::default(),
+            render_cache: RefCell::new(None),
         }
     }
 }
+
+///
+/// The caret notation of a control character: `^@`..`^_` for C0, `^?` for DEL
+/// and `M-^@`..`M-^_` for C1, like `cat -v`. Tabs are left alone.
+///
+fn caret_notation(c: char) -> Option<String> {
+    match c as u32 {
+        0x09 => None,
+        code @ 0x00..=0x1f => Some(format!("^{}", (code as u8 + 0x40) as char)),
+        0x7f => Some("^?".to_string()),
+        code @ 0x80..=0x9f => Some(format!("M-^{}", (code as u8 - 0x40) as char)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ansi::AnsiStripper;
+    use crate::filetype::FileType;
+
+    #[test]
+    fn delete_multibyte_characters_from_the_end() {
+        let mut row = Row::from("héllo→");
+        assert_eq!(row.len(), 6);
+        let mut end = row.len();
+        while end > 0 {
+            end -= 1;
+            row.delete(end);
+            assert_eq!(row.len(), end);
+        }
+        assert_eq!(row.text(), "");
+    }
+
+    #[test]
+    fn multibyte_positions_are_characters() {
+        let mut row = Row::from("héllo→");
+        let query = Pattern::new("→", false).unwrap();
+        assert_eq!(row.find(&query, 0, SearchDirection::Forward), Some(5..6));
+        let rest = row.split(2);
+        assert_eq!(row.text(), "hé");
+        assert_eq!(rest.text(), "llo→");
+    }
+
+    #[test]
+    fn render_highlighted_slice() {
+        let text = "let value = call(1234) + \"string\"; // a comment";
+        let mut row = Row::from(text);
+        let highlighter = highlighting::TokenHighlighter::new(FileType::from("main.rs"));
+        row.highlight(&highlighter, highlighting::State::default(), &None);
+        let rendered = row.render(10, 30, false, 4);
+
+        let visible: String = text.chars().skip(10).take(20).collect();
+        assert_eq!(AnsiStripper::default().strip(&rendered), visible);
+        // each character is colored and reset on its own
+        let first = format!(
+            "{}{}{}",
+            crossterm::SetFg(row.highlighting()[10].to_color()),
+            visible.chars().next().unwrap(),
+            crossterm::SetFg(Color::Reset)
+        );
+        assert!(rendered.starts_with(&first));
+        let last = format!(
+            "{}{}{}",
+            crossterm::SetFg(row.highlighting()[29].to_color()),
+            visible.chars().last().unwrap(),
+            crossterm::SetFg(Color::Reset)
+        );
+        assert!(rendered.ends_with(&last));
+    }
+
+    #[test]
+    fn render_on_a_one_column_or_empty_screen() {
+        let row = Row::from("\thello");
+        assert_eq!(row.render(0, 0, false, 4), "");
+        // the end left of the start, as computed for a screen narrower than the gutter
+        assert_eq!(row.render(8, 2, true, 4), "");
+        let one_column = AnsiStripper::default().strip(&row.render(4, 5, false, 4));
+        assert_eq!(one_column, "h");
+    }
+
+    #[test]
+    fn render_cache_follows_the_text_shading_and_offset() {
+        let cached = |row: &Row| row.render_cache.borrow().as_ref().map(|(key, _)| *key);
+        let mut row = Row::from("hello");
+        let plain = row.render(0, 80, false, 4);
+        assert_eq!(cached(&row), Some((0, 80, false, 4)));
+        assert_eq!(row.render(0, 80, false, 4), plain);
+
+        row.insert('!', 5);
+        assert_eq!(cached(&row), None);
+        let typed = row.render(0, 80, false, 4);
+        assert_eq!(AnsiStripper::default().strip(&typed), "hello!");
+
+        // shading again what is shaded already keeps the cache
+        row.shade(0, 2, highlighting::Type::Selection);
+        assert_eq!(cached(&row), None);
+        let selected = row.render(0, 80, false, 4);
+        assert_ne!(selected, typed);
+        row.shade(0, 2, highlighting::Type::Selection);
+        assert_eq!(cached(&row), Some((0, 80, false, 4)));
+
+        let highlighter = highlighting::TokenHighlighter::new(FileType::from("main.rs"));
+        row.highlight(&highlighter, highlighting::State::default(), &None);
+        assert_eq!(cached(&row), None);
+
+        // scrolled horizontally
+        let scrolled = row.render(2, 80, false, 4);
+        assert_eq!(AnsiStripper::default().strip(&scrolled), "llo!");
+        assert_eq!(cached(&row), Some((2, 80, false, 4)));
+    }
+
+    // a benchmark, run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn render_cache_frame_time() {
+        use std::time::Instant;
+        const FRAMES: usize = 1_000;
+        const SCREEN_ROWS: usize = 60;
+        let highlighter = highlighting::TokenHighlighter::new(FileType::from("main.rs"));
+        let rows: Vec<Row> = (0..SCREEN_ROWS)
+            .map(|index| {
+                let mut row = Row::from(
+                    format!(
+                        "    let value{} = call(1234) + \"string\"; // a comment",
+                        index
+                    )
+                    .as_str(),
+                );
+                row.highlight(&highlighter, highlighting::State::default(), &None);
+                row
+            })
+            .collect();
+        let time = |render: &dyn Fn(&Row) -> String| {
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                for row in &rows {
+                    render(row);
+                }
+            }
+            start.elapsed()
+        };
+        let uncached = time(&|row| row.render_uncached(0, 120, false, 4));
+        let cached = time(&|row| row.render(0, 120, false, 4));
+        println!(
+            "{} frames of {} rows: {:?} without the cache, {:?} with it",
+            FRAMES, SCREEN_ROWS, uncached, cached
+        );
+        assert!(cached < uncached);
+    }
+}
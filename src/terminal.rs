@@ -1,28 +1,38 @@
-use crossterm::input;
 use std::result::Result;
 use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
 use crossterm::RawScreen;
 use crossterm::TerminalCursor;
 use crossterm::ClearType;
 
 use crate::editor::Position;
+use crate::error::EditorError;
 pub type InputEvent = crossterm::InputEvent;
 pub type KeyEvent = crossterm::KeyEvent;
+pub type MouseEvent = crossterm::MouseEvent;
+pub type MouseButton = crossterm::MouseButton;
 
 // Numerical constants for some keys
-const K_ENTER : usize= 0x0D;
-const K_DELETE : usize= 0x53;
-const K_BACKSPACE : usize = 0x8;
-const K_TAB : usize= 0x9;
-const K_PAGEDOWN : usize = 0x51;
-const K_PAGEUP : usize = 0x49;
-const K_HOME : usize = 0x47;
-const K_END : usize = 0x4F;
-const K_ARROW_UP : usize = 0x48;
-const K_ARROW_DOWN : usize = 0x50;
-const K_ARROW_LEFT : usize = 0x4B;
-const K_ARROW_RIGHT : usize = 0x4D;
-const K_ESCAPE : usize = 0x1B;
+#[cfg(windows)]
+mod scancodes {
+    pub const K_ENTER : usize= 0x0D;
+    pub const K_DELETE : usize= 0x53;
+    pub const K_BACKSPACE : usize = 0x8;
+    pub const K_TAB : usize= 0x9;
+    pub const K_PAGEDOWN : usize = 0x51;
+    pub const K_PAGEUP : usize = 0x49;
+    pub const K_HOME : usize = 0x47;
+    pub const K_END : usize = 0x4F;
+    pub const K_ARROW_UP : usize = 0x48;
+    pub const K_ARROW_DOWN : usize = 0x50;
+    pub const K_ARROW_LEFT : usize = 0x4B;
+    pub const K_ARROW_RIGHT : usize = 0x4D;
+    pub const K_ESCAPE : usize = 0x1B;
+    pub const K_INSERT : usize = 0x52;
+}
+#[cfg(windows)]
+use scancodes::*;
 
 
 pub type Color = crossterm::Color;
@@ -33,25 +43,37 @@ pub struct Size {
 
 pub struct Terminal {
     pub size: Size,
-    _stdout : Result<RawScreen, std::io::Error>,
+    _stdout : RawScreen,
     _cursor : TerminalCursor,
     _internal: crossterm::Terminal,
+    // rows kept for the status and message bars
+    bar_rows: usize,
+    // a single reader for the whole session, dropping one may lose a pending key
+    #[cfg(not(windows))]
+    _reader: crossterm::AsyncReader,
 }
 ///
 /// Wrapper around a crossterm terminal with default 
 /// Configuration for the text editor being written.
 /// 
 /// This configuration includes cursor and color setup 
-///     Mouse input disabled
+///     Mouse input disabled, unless enabled with `enable_mouse`
 ///     Raw mode enabled by default
 ///     Keypress events handling
 /// 
 /// 
 #[allow(unused_must_use)]
 impl Terminal {
-    pub fn default() -> Result<Terminal, std::io::Error> {
+    pub fn default() -> Result<Terminal, EditorError> {
         let _terminal = crossterm::Terminal::new();
-        let size = _terminal.size().expect("Could not get terminal size");
+        let size = _terminal.size().map_err(|error| EditorError::Terminal {
+            operation: "get the terminal size",
+            source: error.into(),
+        })?;
+        let raw_screen = RawScreen::into_raw_mode().map_err(|error| EditorError::Terminal {
+            operation: "switch the terminal to raw mode",
+            source: error.into(),
+        })?;
         crossterm::input().disable_mouse_mode();
         Ok(Terminal {
             size: Size {
@@ -60,9 +82,21 @@ impl Terminal {
             },
             _cursor: crossterm::TerminalCursor::new(),
             _internal: _terminal,
-            _stdout: Ok(RawScreen::into_raw_mode().unwrap()),
+            _stdout: raw_screen,
+            bar_rows: 2,
+            #[cfg(not(windows))]
+            _reader: crossterm::input().read_async(),
         })
     }
+    /// Report mouse clicks and wheel scrolls as events, the windows console reads keys only
+    pub fn enable_mouse(&self) {
+        crossterm::input().enable_mouse_mode();
+    }
+    /// Stop reporting the mouse, or the shell gets its escape sequences once we're gone
+    pub fn disable_mouse(&self) {
+        crossterm::input().disable_mouse_mode();
+    }
+
     pub fn cursor_position(&self,position : &Position) {
         let Position{x, y} = position;
         self._cursor.goto(*x as u16, *y as u16);
@@ -71,18 +105,81 @@ impl Terminal {
         let (x,y) = self._cursor.pos().or_else(|_| Err("Something went wrong getting cursor position"))?;
         Ok(Position{x: x as usize,y:y as usize})
     }
-    pub fn read_keypress(&self) -> Option<InputEvent> {
+    pub fn read_keypress(&mut self) -> Option<InputEvent> {
+        loop {
+            if let Some(event) = self.read_event() {
+                return Some(event);
+            }
+        }
+    }
+
+    /// Wait at most `timeout` for a keypress, so the caller can do periodic work when idle.
+    /// The windows console reads block, there this waits for a key regardless of the timeout.
+    pub fn read_keypress_timeout(&mut self, timeout: Duration) -> Option<InputEvent> {
+        let start = Instant::now();
         loop {
-            // match input().read_sync().next() {
-            //     Some(event) => return Some(event),
-            //     _ => (),
-            // };
-            match input().read_char() {
-                Ok(c) => {
-                    return Some(InputEvent::Keyboard(self.map_to_key(c)));
-                }
-                _  => (),
-             };
+            if let Some(event) = self.read_event() {
+                return Some(event);
+            }
+            if start.elapsed() >= timeout {
+                return None;
+            }
+        }
+    }
+
+    /// A keypress already waiting, without blocking.
+    /// The windows console reads block, there nothing is ever reported as waiting.
+    #[cfg(windows)]
+    pub fn poll_keypress(&mut self) -> Option<InputEvent> {
+        None
+    }
+
+    /// A keypress already waiting, without blocking.
+    #[cfg(not(windows))]
+    pub fn poll_keypress(&mut self) -> Option<InputEvent> {
+        self.poll_event()
+    }
+
+    // The windows console does not report shifted symbols through events, read chars instead
+    #[cfg(windows)]
+    fn read_event(&mut self) -> Option<InputEvent> {
+        match crossterm::input().read_char() {
+            Ok(c) => Some(InputEvent::Keyboard(self.map_to_key(c))),
+            _ => None,
+        }
+    }
+
+    // Elsewhere crossterm decodes the escape sequences (arrows, shift + arrows, ...) for us.
+    // Its control keys are turned back into the control byte and decoded like on windows,
+    // so both platforms report them the same way.
+    #[cfg(not(windows))]
+    fn read_event(&mut self) -> Option<InputEvent> {
+        let event = self.poll_event();
+        if event.is_none() {
+            // nothing pending, don't spin the processor
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        event
+    }
+
+    #[cfg(not(windows))]
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        match self._reader.next() {
+            Some(InputEvent::Keyboard(KeyEvent::Ctrl(c))) => {
+                let byte = match c.to_ascii_lowercase() {
+                    // crossterm reads the Backspace key as DEL, 0x08 is really Ctrl + H (replace)
+                    'h' => return Some(InputEvent::Keyboard(KeyEvent::Ctrl('H'))),
+                    c @ 'a'..='z' => c as u8 - b'a' + 1,
+                    // crossterm reports 0x1C..=0x1F as Ctrl + 4..7
+                    c @ '4'..='7' => c as u8 - b'4' + 0x1C,
+                    _ => return Some(InputEvent::Keyboard(KeyEvent::Ctrl(c))),
+                };
+                Some(InputEvent::Keyboard(control_key(byte as char)))
+            }
+            Some(InputEvent::Keyboard(KeyEvent::Null)) => Some(InputEvent::Keyboard(control_key('\0'))),
+            Some(InputEvent::Keyboard(key)) => Some(InputEvent::Keyboard(key)),
+            Some(InputEvent::Mouse(event)) => Some(InputEvent::Mouse(event)),
+            _ => None,
         }
     }
     ///
@@ -113,6 +210,24 @@ impl Terminal {
         crossterm::TerminalColor::new().reset();
     }
 
+    /// Print a bar filling `width` columns over a `color` background, or in reverse video without one.
+    /// The reset is part of the same write so the background can't bleed onto the next rows.
+    pub fn print_bar(&self, text: &str, width: usize, color: Option<Color>) {
+        print!("{}", bar(text, width, color));
+    }
+
+    /// Read the size of the terminal again, returns whether it changed (the window was resized).
+    /// The size is kept when it can't be read.
+    pub fn update_size(&mut self) -> bool {
+        match self._internal.size() {
+            Ok((width, height)) if width != self.size.width || height != self.size.height => {
+                self.size = Size { width, height };
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Get the current size of the terminal
     /// Returns a Size struct which is formated {width, height}
     pub fn size(&self) -> &Size {
@@ -120,9 +235,18 @@ impl Terminal {
     }
 
     /// Helper method since I was lazy to be unpacking width and height from size
-    /// This retrieves the height
+    /// This retrieves the height available for text (the rest is kept for the bars)
+    /// Saturates to zero on terminals too small to hold any text.
     pub fn height(&self) -> usize {
-        self.size.height as usize - 2
+        text_height(self.size.height, self.bar_rows)
+    }
+
+    /// How many rows the bars take, two unless the status bar needs more
+    pub fn bar_rows(&self) -> usize {
+        self.bar_rows
+    }
+    pub fn set_bar_rows(&mut self, rows: usize) {
+        self.bar_rows = rows;
     }
     
     /// Helper method since I was lazy to be unpacking width and height from size
@@ -151,11 +275,6 @@ impl Terminal {
 
     }
 
-    fn is_control_key (&self, c: char) -> bool {
-        let numc = c as usize;
-        return numc < 32;
-    }
-
     // crosterm handles reading chars vs reading events for key pressed indipendently
     // This means that for instance if you press shift + r.
     // You'll catch 'R' when reading characteres but not when reading keypress events.
@@ -163,6 +282,7 @@ impl Terminal {
     // Keyboard codes  here: http://www.philipstorr.id.au/pcbook/book3/scancode.htm
     // Control codes here: https://www.windmill.co.uk/ascii-control-codes.html
     //  ASCII codes here: http://www.asciitable.com/
+    #[cfg(windows)]
     fn map_to_key(&self, c: char) -> KeyEvent {
         let numc = c as usize;
         if numc == K_ENTER {
@@ -191,11 +311,98 @@ impl Terminal {
             return KeyEvent::Right;
         }else if numc == K_ESCAPE {
             return KeyEvent::Esc;
+        } else if numc == K_INSERT {
+            return KeyEvent::Insert;
         }
-        else if self.is_control_key(c) {
-            KeyEvent::Ctrl((c as u8 + 0x40) as char)
+        else if c.is_ascii_control() {
+            control_key(c)
         } else {
             KeyEvent::Char(c)
         }
     }
 }
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.disable_mouse();
+    }
+}
+
+/// `text` cut or padded to `width` characters, colored and reset
+fn bar(text: &str, width: usize, color: Option<Color>) -> String {
+    let mut text: String = text.chars().take(width).collect();
+    let length = text.chars().count();
+    text.push_str(&" ".repeat(width - length));
+    match color {
+        Some(color) => format!(
+            "{}{}{}",
+            crossterm::SetBg(color),
+            text,
+            crossterm::SetBg(Color::Reset)
+        ),
+        None => format!(
+            "{}{}{}",
+            crossterm::SetAttr(crossterm::Attribute::Reverse),
+            text,
+            crossterm::SetAttr(crossterm::Attribute::Reset)
+        ),
+    }
+}
+
+/// Rows left for text on a terminal `height` rows high, none when the bars don't even fit
+fn text_height(height: u16, bar_rows: usize) -> usize {
+    (height as usize).saturating_sub(bar_rows)
+}
+
+///
+/// Decode a control byte the way the editor binds it: `Ctrl` with the upper case
+/// caret notation character (0x01 is `Ctrl('A')`, 0x1C is `Ctrl('\\')`, 0x00 - Ctrl + Space - is `Ctrl(' ')`).
+/// The bytes terminals also send for dedicated keys decode as those keys:
+/// Tab (Ctrl + I), Enter (Ctrl + M, Ctrl + J), Backspace (Ctrl + H, DEL) and Esc (Ctrl + [),
+/// a terminal sends the same byte for both so they cannot be told apart.
+/// Where the reader tells Backspace from Ctrl + H, Ctrl + H doesn't come through here.
+///
+pub fn control_key(c: char) -> KeyEvent {
+    match c {
+        '\t' => KeyEvent::Tab,
+        '\r' | '\n' => KeyEvent::Enter,
+        '\x08' | '\x7f' => KeyEvent::Backspace,
+        '\x1b' => KeyEvent::Esc,
+        '\0' => KeyEvent::Ctrl(' '),
+        '\x01'..='\x1f' => KeyEvent::Ctrl((c as u8 + 0x40) as char),
+        _ => KeyEvent::Char(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_row_terminal_has_no_room_for_text() {
+        assert_eq!(text_height(1, 2), 0);
+        assert_eq!(text_height(0, 2), 0);
+        assert_eq!(text_height(3, 2), 1);
+        assert_eq!(text_height(24, 2), 22);
+    }
+
+    #[test]
+    fn status_bar_fills_its_row_and_resets_the_color() {
+        for color in &[Some(Color::Red), None] {
+            let reset = match color {
+                Some(_) => crossterm::SetBg(Color::Reset).to_string(),
+                None => crossterm::SetAttr(crossterm::Attribute::Reset).to_string(),
+            };
+            for text in &["short", "a status much wider than the screen", "fiché"] {
+                let bar = bar(text, 12, *color);
+                // nothing is printed after the reset, the next row starts uncolored
+                assert!(bar.ends_with(&reset));
+                let body = &bar[..bar.len() - reset.len()];
+                let visible: String =
+                    text.chars().chain(std::iter::repeat(' ')).take(12).collect();
+                assert!(body.ends_with(&visible));
+                assert!(!bar.contains('\n'));
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::editor::Position;
+
+/// How many undo steps are kept, the oldest ones are forgotten first
+const MAX_UNDO_STEPS: usize = 1000;
+
+///
+/// A change to the text of a document, with what it takes to reverse it
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// A character (not a new line) was inserted at the position
+    Insert(Position, char),
+    /// The character at the position was deleted
+    Delete(Position, char),
+    /// The row was split in two at the position
+    Split(Position),
+    /// The next row was appended to the row, which was `x` characters long
+    Join(Position),
+    /// The rows from `first` reading `before` were replaced by the ones reading `after`
+    Rows {
+        first: usize,
+        before: Vec<String>,
+        after: Vec<String>,
+    },
+}
+
+impl Edit {
+    /// Where the text was before the edit, where the cursor goes back to on undo
+    pub fn start(&self) -> Position {
+        match self {
+            Edit::Insert(position, _)
+            | Edit::Delete(position, _)
+            | Edit::Split(position)
+            | Edit::Join(position) => position.clone(),
+            Edit::Rows { first, .. } => Position { x: 0, y: *first },
+        }
+    }
+
+    /// Where the text ends after the edit, where the cursor goes on redo
+    pub fn end(&self) -> Position {
+        match self {
+            Edit::Insert(position, _) => Position {
+                x: position.x + 1,
+                y: position.y,
+            },
+            Edit::Split(position) => Position {
+                x: 0,
+                y: position.y + 1,
+            },
+            Edit::Delete(position, _) | Edit::Join(position) => position.clone(),
+            Edit::Rows { first, .. } => Position { x: 0, y: *first },
+        }
+    }
+
+    /// The last row the edit touches, before or after it's made
+    pub fn last_row(&self) -> usize {
+        match self {
+            Edit::Insert(position, _) | Edit::Delete(position, _) => position.y,
+            Edit::Split(position) | Edit::Join(position) => position.y + 1,
+            Edit::Rows {
+                first,
+                before,
+                after,
+            } => first + before.len().max(after.len()),
+        }
+    }
+}
+
+///
+/// The edits undone (or redone) by a single step, in the order they were made
+///
+#[derive(Debug, Default)]
+pub struct Step {
+    pub edits: Vec<Edit>,
+}
+
+///
+/// The undo and redo stacks of a document
+///
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    // the last step takes the following edits too (a batch is in progress)
+    open: bool,
+    // the last step was closed, the next edit starts a new one whatever it is
+    sealed: bool,
+}
+
+impl History {
+    ///
+    /// Add an edit to the history, which drops whatever was undone.
+    /// Typing coalesces into the previous step until a word ends.
+    ///
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        let coalesces = self.open
+            || (!self.sealed
+                && self
+                    .undo
+                    .last()
+                    .map_or(false, |step| continues(step, &edit)));
+        self.sealed = false;
+        match self.undo.last_mut() {
+            Some(step) if coalesces => step.edits.push(edit),
+            _ => {
+                self.undo.push(Step { edits: vec![edit] });
+                if self.undo.len() > MAX_UNDO_STEPS {
+                    self.undo.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Make the following edits a single step, until `close_step`
+    pub fn open_step(&mut self) {
+        if !self.open {
+            self.open = true;
+            self.undo.push(Step::default());
+        }
+    }
+
+    /// End the step opened by `open_step`, it's dropped when nothing was recorded
+    pub fn close_step(&mut self) {
+        self.open = false;
+        self.sealed = true;
+        if self.undo.last().map_or(false, |step| step.edits.is_empty()) {
+            self.undo.pop();
+        }
+    }
+
+    /// The step to undo, to be handed back with `undone` once reversed
+    pub fn take_undo(&mut self) -> Option<Step> {
+        self.undo.pop()
+    }
+
+    pub fn undone(&mut self, step: Step) {
+        self.redo.push(step);
+        self.sealed = true;
+    }
+
+    /// The step to redo, to be handed back with `redone` once made again
+    pub fn take_redo(&mut self) -> Option<Step> {
+        self.redo.pop()
+    }
+
+    pub fn redone(&mut self, step: Step) {
+        self.undo.push(step);
+        self.sealed = true;
+    }
+}
+
+/// Whether `edit` types on right after the last edit of `step`, in the same word
+fn continues(step: &Step, edit: &Edit) -> bool {
+    match (step.edits.last(), edit) {
+        (Some(Edit::Insert(previous, previous_char)), Edit::Insert(position, c)) => {
+            position.y == previous.y
+                && position.x == previous.x + 1
+                && (!c.is_whitespace() || previous_char.is_whitespace())
+        }
+        _ => false,
+    }
+}
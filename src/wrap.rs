@@ -0,0 +1,93 @@
+///
+/// Reflow the paragraphs of `lines` so no line is longer than `width` characters,
+/// breaking at word boundaries. Blank lines separate paragraphs and are kept.
+/// With `markdown`, fenced code blocks, headings and tables are left alone and
+/// list items or quotes start a new paragraph.
+/// Returns the new lines and how many of the original lines were reformatted.
+///
+pub fn hard_wrap(lines: &[&str], width: usize, markdown: bool) -> (Vec<String>, usize) {
+    let width = width.max(1);
+    let mut result = Vec::new();
+    let mut reformatted = 0;
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+
+    for line in lines {
+        let trimmed = line.trim_start();
+        let is_fence = markdown && (trimmed.starts_with("```") || trimmed.starts_with("~~~"));
+        let verbatim = in_fence
+            || is_fence
+            || trimmed.is_empty()
+            || (markdown && (trimmed.starts_with('#') || trimmed.starts_with('|')));
+        if verbatim || (markdown && starts_block(trimmed)) {
+            reformatted += flush(&mut paragraph, width, &mut result);
+        }
+        if is_fence {
+            in_fence = !in_fence;
+        }
+        if verbatim {
+            result.push(line.to_string());
+        } else {
+            paragraph.push(line);
+        }
+    }
+    reformatted += flush(&mut paragraph, width, &mut result);
+    (result, reformatted)
+}
+
+/// Markdown lines starting a block of their own: list items and quotes
+fn starts_block(line: &str) -> bool {
+    if line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ") {
+        return true;
+    }
+    if line.starts_with('>') {
+        return true;
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    digits > 0 && (line[digits..].starts_with(". ") || line[digits..].starts_with(") "))
+}
+
+/// Wrap the pending paragraph into `result`, returns how many of its lines changed
+fn flush(paragraph: &mut Vec<&str>, width: usize, result: &mut Vec<String>) -> usize {
+    if paragraph.is_empty() {
+        return 0;
+    }
+    // the paragraph keeps the indentation of its first line
+    let indent: String = paragraph[0]
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect();
+    let mut wrapped: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in paragraph.iter().flat_map(|line| line.split_whitespace()) {
+        let len = current.chars().count();
+        if len > 0 && len + 1 + word.chars().count() > width {
+            wrapped.push(current);
+            current = String::new();
+        }
+        if current.is_empty() {
+            current.push_str(&indent);
+        } else {
+            current.push(' ');
+        }
+        // words longer than the width get a line of their own
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        wrapped.push(current);
+    }
+
+    let changed = if wrapped.len() == paragraph.len()
+        && wrapped
+            .iter()
+            .zip(paragraph.iter())
+            .all(|(new, old)| new == old)
+    {
+        0
+    } else {
+        paragraph.len()
+    };
+    result.extend(wrapped);
+    paragraph.clear();
+    changed
+}